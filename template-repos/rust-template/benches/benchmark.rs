@@ -65,6 +65,18 @@ fn benchmark_rate_limiter(c: &mut Criterion) {
     });
 }
 
+fn benchmark_rate_limiter_full_window(c: &mut Criterion) {
+    // A saturated limiter keeps its whole window buffered on every check
+    let rate_limiter = utils::RateLimiter::new(10_000, std::time::Duration::from_secs(60));
+    while rate_limiter.is_allowed() {}
+
+    c.bench_function("rate_limiter_check_full_window", |b| {
+        b.iter(|| {
+            rate_limiter.is_allowed()
+        })
+    });
+}
+
 fn benchmark_metrics_collector(c: &mut Criterion) {
     let metrics = utils::MetricsCollector::new();
     
@@ -80,6 +92,16 @@ fn benchmark_metrics_collector(c: &mut Criterion) {
         })
     });
     
+    c.bench_function("metrics_increment_labeled_counter", |b| {
+        b.iter(|| {
+            metrics.increment_labeled_counter(
+                black_box("test_requests"),
+                black_box(&[("route", "/process"), ("method", "POST")]),
+                black_box(1),
+            )
+        })
+    });
+    
     c.bench_function("metrics_get_json", |b| {
         b.iter(|| {
             metrics.get_metrics_json().unwrap()
@@ -87,6 +109,76 @@ fn benchmark_metrics_collector(c: &mut Criterion) {
     });
 }
 
+fn benchmark_metrics_json_under_contention(c: &mut Criterion) {
+    let metrics = utils::MetricsCollector::new();
+    for i in 0..200 {
+        metrics.increment_counter(&format!("counter_{}", i), 1);
+        metrics.set_gauge(&format!("gauge_{}", i), i as f64);
+    }
+
+    // Writers hammer the collector for the whole measurement
+    let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let writers: Vec<_> = (0..4)
+        .map(|writer| {
+            let metrics = metrics.clone();
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                let counter = format!("counter_{}", writer);
+                let gauge = format!("gauge_{}", writer);
+                while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                    metrics.increment_counter(&counter, 1);
+                    metrics.set_gauge(&gauge, 1.0);
+                }
+            })
+        })
+        .collect();
+
+    c.bench_function("metrics_get_json_contended", |b| {
+        b.iter(|| {
+            metrics.get_metrics_json().unwrap()
+        })
+    });
+
+    stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    for writer in writers {
+        writer.join().unwrap();
+    }
+}
+
+fn benchmark_counter_increments_contended(c: &mut Criterion) {
+    let metrics = utils::MetricsCollector::new();
+    let handle = metrics.register_counter("hot_counter");
+
+    // Four threads each add 1000 to the same counter per iteration
+    c.bench_function("metrics_increment_counter_contended", |b| {
+        b.iter(|| {
+            std::thread::scope(|scope| {
+                for _ in 0..4 {
+                    scope.spawn(|| {
+                        for _ in 0..1000 {
+                            metrics.increment_counter(black_box("hot_counter"), black_box(1));
+                        }
+                    });
+                }
+            })
+        })
+    });
+
+    c.bench_function("metrics_counter_handle_contended", |b| {
+        b.iter(|| {
+            std::thread::scope(|scope| {
+                for _ in 0..4 {
+                    scope.spawn(|| {
+                        for _ in 0..1000 {
+                            handle.inc(black_box(1));
+                        }
+                    });
+                }
+            })
+        })
+    });
+}
+
 fn benchmark_config_validation(c: &mut Criterion) {
     let config = project_name::Config::default();
     
@@ -127,7 +219,10 @@ criterion_group!(
     benchmark_email_validation,
     benchmark_random_string_generation,
     benchmark_rate_limiter,
+    benchmark_rate_limiter_full_window,
     benchmark_metrics_collector,
+    benchmark_metrics_json_under_contention,
+    benchmark_counter_increments_contended,
     benchmark_config_validation,
     benchmark_error_creation,
     benchmark_health_checker
@@ -217,11 +217,12 @@ create_example_config() {
     if [ ! -f "config.example.json" ]; then
         cat > config.example.json << 'EOF'
 {
+  "version": 2,
   "server": {
     "host": "127.0.0.1",
     "port": 8080,
     "max_connections": 1000,
-    "timeout": 30,
+    "request_timeout": "30s",
     "tls_enabled": false
   },
   "database": {
@@ -0,0 +1,279 @@
+//! Request authentication backends.
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::time::Duration;
+
+use crate::config::SecurityConfig;
+use crate::error::{Error, Result};
+use crate::http::HeaderMap;
+use crate::utils::{constant_time_eq, current_timestamp, parse_authorization};
+
+/// An authenticated caller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    /// Who the caller is (JWT subject or API key label)
+    pub subject: String,
+
+    /// Scheme used to authenticate (e.g. `jwt`, `api-key`)
+    pub scheme: &'static str,
+}
+
+/// Authenticates a request from its headers.
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    /// Authenticate the request, failing with [`Error::Auth`] if rejected.
+    async fn authenticate(&self, headers: &HeaderMap) -> Result<Identity>;
+}
+
+/// JWT claims issued and accepted by [`JwtAuthenticator`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    /// Subject
+    pub sub: String,
+
+    /// Issued at (seconds since Unix epoch)
+    pub iat: u64,
+
+    /// Expiry (seconds since Unix epoch)
+    pub exp: u64,
+}
+
+/// HS256 JWT bearer token authentication.
+pub struct JwtAuthenticator {
+    secret: Vec<u8>,
+    expiration: Duration,
+}
+
+impl JwtAuthenticator {
+    const HEADER: &'static str = r#"{"alg":"HS256","typ":"JWT"}"#;
+
+    /// Create an authenticator with the given signing secret and token lifetime.
+    pub fn new(secret: impl Into<Vec<u8>>, expiration: Duration) -> Self {
+        Self {
+            secret: secret.into(),
+            expiration,
+        }
+    }
+
+    /// Create an authenticator from the security configuration.
+    pub fn from_config(config: &SecurityConfig) -> Self {
+        Self::new(config.jwt_secret.as_bytes(), config.jwt_expiration)
+    }
+
+    /// Issue a signed token for a subject.
+    pub fn issue_token(&self, subject: &str) -> Result<String> {
+        let now = current_timestamp();
+        let claims = Claims {
+            sub: subject.to_string(),
+            iat: now,
+            exp: now + self.expiration.as_secs(),
+        };
+
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(Self::HEADER),
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims)?)
+        );
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&signing_input)?.finalize().into_bytes());
+
+        Ok(format!("{}.{}", signing_input, signature))
+    }
+
+    /// Verify a token's signature and expiry, returning its claims.
+    pub fn verify_token(&self, token: &str) -> Result<Claims> {
+        let invalid = || Error::Auth("Invalid token".to_string());
+
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+
+        let decoded_header: serde_json::Value = URL_SAFE_NO_PAD.decode(header).ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(invalid)?;
+        if decoded_header["alg"] != "HS256" {
+            return Err(Error::Auth("Unsupported token algorithm".to_string()));
+        }
+
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+        self.mac(&format!("{}.{}", header, payload))?
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+
+        let claims: Claims = URL_SAFE_NO_PAD.decode(payload).ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(invalid)?;
+
+        if claims.exp <= current_timestamp() {
+            return Err(Error::Auth("Token expired".to_string()));
+        }
+
+        Ok(claims)
+    }
+
+    fn mac(&self, signing_input: &str) -> Result<Hmac<Sha256>> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret)
+            .map_err(|e| Error::Internal(format!("Invalid JWT secret: {}", e)))?;
+        mac.update(signing_input.as_bytes());
+        Ok(mac)
+    }
+}
+
+#[async_trait]
+impl Authenticator for JwtAuthenticator {
+    async fn authenticate(&self, headers: &HeaderMap) -> Result<Identity> {
+        let token = headers
+            .get("Authorization")
+            .and_then(|value| parse_authorization(value).ok())
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Bearer"))
+            .map(|(_, token)| token)
+            .ok_or_else(|| Error::Auth("Missing bearer token".to_string()))?;
+
+        let claims = self.verify_token(&token)?;
+
+        Ok(Identity {
+            subject: claims.sub,
+            scheme: "jwt",
+        })
+    }
+}
+
+/// Static API key authentication via a request header.
+pub struct ApiKeyAuthenticator {
+    header: String,
+    keys: Vec<String>,
+}
+
+impl ApiKeyAuthenticator {
+    /// Default header carrying the API key.
+    pub const DEFAULT_HEADER: &'static str = "X-API-Key";
+
+    /// Create an authenticator accepting any of the given keys.
+    pub fn new(keys: Vec<String>) -> Self {
+        Self {
+            header: Self::DEFAULT_HEADER.to_string(),
+            keys,
+        }
+    }
+
+    /// Create an authenticator from the security configuration.
+    pub fn from_config(config: &SecurityConfig) -> Self {
+        Self::new(config.api_keys.clone())
+    }
+
+    /// Read the key from a different header.
+    pub fn with_header(mut self, header: impl Into<String>) -> Self {
+        self.header = header.into();
+        self
+    }
+}
+
+#[async_trait]
+impl Authenticator for ApiKeyAuthenticator {
+    async fn authenticate(&self, headers: &HeaderMap) -> Result<Identity> {
+        let presented = headers
+            .get(&self.header)
+            .map(str::trim)
+            .ok_or_else(|| Error::Auth(format!("Missing {} header", self.header)))?;
+
+        // Check every key so timing doesn't reveal which one matched
+        let matched = self.keys.iter().enumerate().fold(None, |matched, (index, key)| {
+            if constant_time_eq(presented.as_bytes(), key.as_bytes()) {
+                Some(index)
+            } else {
+                matched
+            }
+        });
+
+        match matched {
+            Some(index) => Ok(Identity {
+                subject: format!("api-key:{}", index),
+                scheme: "api-key",
+            }),
+            None => Err(Error::Auth("Invalid API key".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(name: &str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, value);
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_jwt_authenticator() -> Result<()> {
+        let auth = JwtAuthenticator::new("a-secret-that-is-long-enough-for-hs256", Duration::from_secs(60));
+        let token = auth.issue_token("alice")?;
+
+        let identity = auth.authenticate(&headers("Authorization", &format!("Bearer {}", token))).await?;
+        assert_eq!(identity.subject, "alice");
+        assert_eq!(identity.scheme, "jwt");
+
+        // Tampered signature
+        let tampered = format!("{}x", token);
+        let result = auth.authenticate(&headers("Authorization", &format!("Bearer {}", tampered))).await;
+        assert!(matches!(result, Err(Error::Auth(_))));
+
+        // Signed with a different secret
+        let other = JwtAuthenticator::new("a-different-secret-entirely-123456", Duration::from_secs(60));
+        let result = auth.authenticate(&headers("Authorization", &format!("Bearer {}", other.issue_token("alice")?))).await;
+        assert!(matches!(result, Err(Error::Auth(_))));
+
+        // Missing header
+        assert!(matches!(auth.authenticate(&HeaderMap::new()).await, Err(Error::Auth(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_jwt_expired_token() -> Result<()> {
+        let auth = JwtAuthenticator::new("a-secret-that-is-long-enough-for-hs256", Duration::ZERO);
+        let token = auth.issue_token("alice")?;
+
+        let result = auth.verify_token(&token);
+        assert!(matches!(result, Err(Error::Auth(message)) if message.contains("expired")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_jwt_expiration_from_config() -> Result<()> {
+        let config = SecurityConfig {
+            jwt_secret: "a-secret-that-is-long-enough-for-hs256".to_string(),
+            jwt_expiration: Duration::from_secs(15 * 60),
+            ..SecurityConfig::default()
+        };
+        let auth = JwtAuthenticator::from_config(&config);
+        let claims = auth.verify_token(&auth.issue_token("alice")?)?;
+        assert_eq!(claims.exp - claims.iat, 15 * 60);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_api_key_authenticator() -> Result<()> {
+        let auth = ApiKeyAuthenticator::new(vec!["key-one".to_string(), "key-two".to_string()]);
+
+        let identity = auth.authenticate(&headers("x-api-key", "key-two")).await?;
+        assert_eq!(identity.scheme, "api-key");
+
+        let result = auth.authenticate(&headers("X-API-Key", "key-three")).await;
+        assert!(matches!(result, Err(Error::Auth(_))));
+
+        let result = auth.authenticate(&HeaderMap::new()).await;
+        assert!(matches!(result, Err(Error::Auth(_))));
+
+        Ok(())
+    }
+}
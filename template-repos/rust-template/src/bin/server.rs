@@ -1,10 +1,16 @@
-use clap::{Parser, Subcommand};
-use tokio::net::TcpListener;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tracing::{info, error, warn};
-use tracing_subscriber;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
 
-use project_name::{Config, Result, process_data};
+use clap::{Parser, Subcommand, ValueEnum};
+use futures::stream::{self, StreamExt};
+use tracing::{info, warn};
+
+use project_name::config::{ConfigOverlay, LoggingConfig, Port, ServerOverlay};
+use project_name::error::BatchError;
+use project_name::server::Server;
+use project_name::utils::Uptime;
+use project_name::{Config, Error, ProcessOptions, Result, process_data_detailed};
 
 #[derive(Parser)]
 #[command(name = "server")]
@@ -28,7 +34,7 @@ struct Cli {
     
     /// Server port
     #[arg(short, long, default_value = "8080")]
-    port: u16,
+    port: Port,
 }
 
 #[derive(Subcommand)]
@@ -41,22 +47,65 @@ enum Commands {
     },
     /// Run health check
     Health,
-    /// Process data from stdin
+    /// Check that the server would start, without accepting connections
+    Check {
+        /// Check the TLS startup path
+        #[arg(long)]
+        tls: bool,
+    },
+    /// Inspect configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Process data from stdin or files, one line at a time
     Process {
         /// Input data
-        #[arg(short, long)]
+        #[arg(short, long, conflicts_with = "files")]
         input: Option<String>,
+        
+        /// Input files, processed in place of stdin
+        files: Vec<PathBuf>,
+        
+        /// Number of files processed in parallel
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+        concurrency: u16,
+        
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+        
+        /// Report failing lines and keep going instead of aborting
+        #[arg(long)]
+        continue_on_error: bool,
     },
 }
 
+/// Output format for the process command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Plain text, one result per line
+    Text,
+    /// JSON Lines, one object per input line
+    Json,
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// List the environment variables the configuration reads
+    Env,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
     
     // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(&cli.log_level)
-        .init();
+    project_name::logging::try_init(&LoggingConfig {
+        level: cli.log_level.clone(),
+        format: "full".to_string(),
+        ..LoggingConfig::default()
+    })?;
     
     info!("Starting server application");
     
@@ -69,11 +118,21 @@ async fn main() -> Result<()> {
     };
     
     // Override config with CLI arguments
-    config.server.host = cli.host;
-    config.server.port = cli.port;
+    config.merge(ConfigOverlay {
+        server: ServerOverlay { host: Some(cli.host), port: Some(cli.port), ..ServerOverlay::default() },
+        ..ConfigOverlay::default()
+    });
     
-    // Validate configuration
-    config.validate()?;
+    // `check` reports validation failures itself
+    if !matches!(cli.command, Some(Commands::Check { .. })) {
+        if let Err(errors) = config.validate_all() {
+            eprintln!("Invalid configuration:");
+            for error in &errors {
+                eprintln!("  - {}", error);
+            }
+            return Err(Error::Config(format!("{} configuration error(s)", errors.len())));
+        }
+    }
     
     match cli.command {
         Some(Commands::Serve { tls }) => {
@@ -81,10 +140,22 @@ async fn main() -> Result<()> {
             start_server(config).await
         }
         Some(Commands::Health) => {
-            run_health_check().await
+            run_health_check(&config).await
         }
-        Some(Commands::Process { input }) => {
-            run_process_command(input).await
+        Some(Commands::Check { tls }) => {
+            config.server.tls_enabled |= tls;
+            run_check_command(&config).await
+        }
+        Some(Commands::Config { action: ConfigCommand::Env }) => {
+            print_env_table();
+            Ok(())
+        }
+        Some(Commands::Process { input, files, concurrency, output, continue_on_error }) => {
+            if files.is_empty() {
+                run_process_command(input, output, continue_on_error).await
+            } else {
+                run_process_files(files, usize::from(concurrency), output, continue_on_error).await
+            }
         }
         None => {
             // Default to serving
@@ -94,124 +165,39 @@ async fn main() -> Result<()> {
 }
 
 async fn start_server(config: Config) -> Result<()> {
-    let address = config.server_address();
-    info!("Starting HTTP server on {}", address);
-    
-    let listener = TcpListener::bind(&address).await
-        .map_err(|e| project_name::Error::Network(format!("Failed to bind to {}: {}", address, e)))?;
-    
-    info!("Server listening on {}", address);
-    
-    loop {
-        match listener.accept().await {
-            Ok((mut socket, addr)) => {
-                info!("New connection from {}", addr);
-                
-                tokio::spawn(async move {
-                    if let Err(e) = handle_connection(&mut socket).await {
-                        error!("Error handling connection from {}: {:?}", addr, e);
-                    }
-                });
-            }
-            Err(e) => {
-                error!("Failed to accept connection: {}", e);
-            }
-        }
+    let uptime = Uptime::new();
+    Server::new(config).with_uptime(uptime).run().await
+}
+
+fn print_env_table() {
+    println!("{:<20} {:<30} {:<5} VALUE", "VARIABLE", "FIELD", "SET");
+    for var in project_name::config::describe_env() {
+        println!(
+            "{:<20} {:<30} {:<5} {}",
+            var.name,
+            var.field,
+            if var.is_set { "yes" } else { "no" },
+            var.value.unwrap_or_default()
+        );
     }
 }
 
-async fn handle_connection(socket: &mut tokio::net::TcpStream) -> Result<()> {
-    let mut buffer = [0; 1024];
-    let bytes_read = socket.read(&mut buffer).await
-        .map_err(|e| project_name::Error::Network(format!("Failed to read from socket: {}", e)))?;
-    
-    let request = String::from_utf8_lossy(&buffer[..bytes_read]);
-    info!("Received request: {}", request.lines().next().unwrap_or(""));
-    
-    // Parse HTTP request (basic parsing)
-    let (method, path) = parse_request_line(&request)?;
+async fn run_health_check(config: &Config) -> Result<()> {
+    info!("Running health check");
     
-    let response = match (method.as_str(), path.as_str()) {
-        ("GET", "/") => {
-            create_response(200, "OK", "text/html", 
-                           "<h1>Hello from Rust Server!</h1><p>Server is running.</p>")
-        }
-        ("GET", "/health") => {
-            create_response(200, "OK", "application/json", 
-                           r#"{"status":"healthy","timestamp":"#.to_string() + &project_name::utils::current_timestamp().to_string() + "}")
-        }
-        ("POST", "/process") => {
-            // Extract body from request (simplified)
-            let body = extract_body(&request);
-            match process_data(&body) {
-                Ok(result) => {
-                    let json_response = format!(r#"{{"result":"{}","status":"success"}}"#, result);
-                    create_response(200, "OK", "application/json", &json_response)
-                }
-                Err(e) => {
-                    let json_response = format!(r#"{{"error":"{}","status":"error"}}"#, e);
-                    create_response(400, "Bad Request", "application/json", &json_response)
-                }
-            }
-        }
-        ("GET", "/metrics") => {
-            // Simple metrics endpoint
-            let metrics = format!(r#"{{
-                "uptime_seconds": {},
-                "requests_total": 1,
-                "status": "healthy"
-            }}"#, project_name::utils::current_timestamp());
-            create_response(200, "OK", "application/json", &metrics)
+    match run_readiness_checks(config).await {
+        Ok(()) => {
+            info!("Health check passed");
+            Ok(())
         }
-        _ => {
-            create_response(404, "Not Found", "text/html", 
-                           "<h1>404 Not Found</h1><p>The requested resource was not found.</p>")
+        Err(e) => {
+            e.log_with("Health check failed");
+            Err(e)
         }
-    };
-    
-    socket.write_all(response.as_bytes()).await
-        .map_err(|e| project_name::Error::Network(format!("Failed to write response: {}", e)))?;
-    
-    Ok(())
-}
-
-fn parse_request_line(request: &str) -> Result<(String, String)> {
-    let first_line = request.lines().next()
-        .ok_or_else(|| project_name::Error::InvalidInput("Empty request".to_string()))?;
-    
-    let parts: Vec<&str> = first_line.split_whitespace().collect();
-    if parts.len() < 2 {
-        return Err(project_name::Error::InvalidInput("Invalid request line".to_string()));
     }
-    
-    Ok((parts[0].to_string(), parts[1].to_string()))
 }
 
-fn extract_body(request: &str) -> String {
-    // Find the empty line that separates headers from body
-    if let Some(body_start) = request.find("\r\n\r\n") {
-        request[body_start + 4..].to_string()
-    } else if let Some(body_start) = request.find("\n\n") {
-        request[body_start + 2..].to_string()
-    } else {
-        String::new()
-    }
-}
-
-fn create_response(status_code: u16, status_text: &str, content_type: &str, body: &str) -> String {
-    format!(
-        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
-        status_code,
-        status_text,
-        content_type,
-        body.len(),
-        body
-    )
-}
-
-async fn run_health_check() -> Result<()> {
-    info!("Running health check");
-    
+async fn run_readiness_checks(config: &Config) -> Result<()> {
     // Perform basic health checks
     let mut checker = project_name::utils::HealthChecker::new();
     
@@ -229,69 +215,389 @@ async fn run_health_check() -> Result<()> {
         Ok(())
     });
     
-    match checker.check_health() {
-        Ok(()) => {
-            info!("Health check passed");
-            Ok(())
-        }
-        Err(e) => {
-            error!("Health check failed: {:?}", e);
-            Err(e)
+    // Check the log directory can still be written to
+    if let Some(log_file) = &config.logging.file_path {
+        let log_dir = match log_file.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => std::path::PathBuf::from("."),
+        };
+        checker.add_check(move || project_name::health::check_writable(&log_dir));
+    }
+    
+    let health_timeout = config.server.request_timeout;
+    match tokio::time::timeout(
+        health_timeout,
+        tokio::task::spawn_blocking(move || checker.check_health()),
+    ).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => Err(Error::Internal(format!("Health check task failed: {}", e))),
+        Err(_) => Err(Error::Timeout(format!(
+            "Health checks did not finish within {:?}", health_timeout
+        ))),
+    }
+}
+
+/// Outcome of one dry-run startup check.
+struct CheckOutcome {
+    name: &'static str,
+    result: Result<()>,
+}
+
+async fn run_check_command(config: &Config) -> Result<()> {
+    let outcomes = startup_checks(config).await;
+    let failed = write_check_report(&outcomes, &mut std::io::stdout().lock())?;
+    
+    if failed > 0 {
+        return Err(Error::Config(format!("{} startup check(s) failed", failed)));
+    }
+    Ok(())
+}
+
+/// Run every step of server startup short of accepting connections.
+async fn startup_checks(config: &Config) -> Vec<CheckOutcome> {
+    let validation = if config.is_production() {
+        config.validate_strict()
+    } else {
+        config.validate()
+    };
+    
+    vec![
+        CheckOutcome { name: "configuration", result: validation },
+        CheckOutcome { name: "tls files", result: check_tls_files(config) },
+        CheckOutcome { name: "bind address", result: resolve_bind_address(config).await },
+        CheckOutcome { name: "health checks", result: run_readiness_checks(config).await },
+    ]
+}
+
+/// Make sure the TLS certificate and key can be read when TLS is enabled.
+fn check_tls_files(config: &Config) -> Result<()> {
+    if !config.server.tls_enabled {
+        return Ok(());
+    }
+    
+    let files = [
+        ("certificate", &config.server.tls_cert_path),
+        ("private key", &config.server.tls_key_path),
+    ];
+    for (label, path) in files {
+        let path = path.as_ref()
+            .ok_or_else(|| Error::Config(format!("TLS is enabled but no {} path is set", label)))?;
+        std::fs::File::open(path)
+            .map_err(|e| Error::Config(format!("Cannot read TLS {} {}: {}", label, path.display(), e)))?;
+    }
+    Ok(())
+}
+
+async fn resolve_bind_address(config: &Config) -> Result<()> {
+    let address = config.server_address();
+    let mut resolved = tokio::net::lookup_host(&address).await
+        .map_err(|e| Error::Network(format!("Cannot resolve bind address {}: {}", address, e)))?;
+    
+    if resolved.next().is_none() {
+        return Err(Error::Network(format!("Bind address {} resolved to nothing", address)));
+    }
+    Ok(())
+}
+
+/// Print one PASS/FAIL line per check, returning the number of failures.
+fn write_check_report<W: Write>(outcomes: &[CheckOutcome], out: &mut W) -> Result<usize> {
+    let mut failed = 0;
+    
+    for outcome in outcomes {
+        match &outcome.result {
+            Ok(()) => writeln!(out, "PASS  {}", outcome.name)?,
+            Err(e) => {
+                failed += 1;
+                writeln!(out, "FAIL  {}: {}", outcome.name, e)?;
+            }
         }
     }
+    
+    Ok(failed)
 }
 
-async fn run_process_command(input: Option<String>) -> Result<()> {
+async fn run_process_command(
+    input: Option<String>,
+    output: OutputFormat,
+    continue_on_error: bool,
+) -> Result<()> {
     let data = if let Some(input) = input {
         input
     } else {
         info!("Reading from stdin...");
         let mut buffer = String::new();
-        std::io::stdin().read_line(&mut buffer)
-            .map_err(|e| project_name::Error::Io(e))?;
-        buffer.trim().to_string()
+        std::io::stdin().read_to_string(&mut buffer)
+            .map_err(Error::Io)?;
+        buffer
     };
     
-    info!("Processing input: {}", data);
+    let stdout = std::io::stdout();
+    let failures = process_lines(data.lines(), output, continue_on_error, &mut stdout.lock())?;
     
-    match process_data(&data) {
-        Ok(result) => {
-            info!("Result: {}", result);
-            println!("{}", result);
-            Ok(())
+    if !failures.is_empty() {
+        warn!("Processing lines: {}", failures);
+    }
+    
+    Ok(())
+}
+
+async fn run_process_files(
+    files: Vec<PathBuf>,
+    concurrency: usize,
+    output: OutputFormat,
+    continue_on_error: bool,
+) -> Result<()> {
+    let read = |path: &PathBuf| {
+        std::fs::read_to_string(path).map_err(|e| Error::Io(e).context(&path.display().to_string()))
+    };
+    
+    let stdout = std::io::stdout();
+    let failed = process_inputs(files, read, concurrency, output, continue_on_error, &mut stdout.lock()).await?;
+    
+    if failed > 0 {
+        warn!("{} line(s) or file(s) failed to process", failed);
+    }
+    
+    Ok(())
+}
+
+/// Load and process up to `concurrency` inputs at a time.
+///
+/// Each input's results are written as a block, in the order the inputs
+/// were given, whatever order they finish in. Returns the number of failed
+/// lines plus inputs that could not be read. Without `continue_on_error`
+/// the first failure stops the run and no further inputs are started.
+async fn process_inputs<I, F, W>(
+    inputs: Vec<I>,
+    read: F,
+    concurrency: usize,
+    output: OutputFormat,
+    continue_on_error: bool,
+    out: &mut W,
+) -> Result<usize>
+where
+    I: Send + 'static,
+    F: Fn(&I) -> Result<String> + Send + Sync + 'static,
+    W: Write,
+{
+    let read = Arc::new(read);
+    let mut results = stream::iter(inputs)
+        .map(|input| {
+            let read = read.clone();
+            tokio::task::spawn_blocking(move || {
+                let data = read(&input)?;
+                let mut buffer = Vec::new();
+                let result = process_lines(data.lines(), output, continue_on_error, &mut buffer);
+                Ok((buffer, result))
+            })
+        })
+        .buffered(concurrency.max(1));
+    
+    let mut failed = 0;
+    while let Some(joined) = results.next().await {
+        let loaded: Result<(Vec<u8>, Result<BatchError>)> =
+            joined.map_err(|e| Error::Internal(format!("Processing task failed: {}", e)))?;
+        
+        match loaded {
+            Ok((buffer, result)) => {
+                out.write_all(&buffer)?;
+                failed += result?.len();
+            }
+            Err(e) if continue_on_error => {
+                e.log_with("Reading input failed");
+                failed += 1;
+            }
+            Err(e) => return Err(e),
         }
-        Err(e) => {
-            error!("Processing failed: {:?}", e);
-            Err(e)
+    }
+    
+    Ok(failed)
+}
+
+/// Process each line, writing results in the requested format.
+///
+/// Returns the failed lines by index. Without `continue_on_error` the
+/// first failure aborts the run.
+fn process_lines<'a, W: Write>(
+    lines: impl IntoIterator<Item = &'a str>,
+    output: OutputFormat,
+    continue_on_error: bool,
+    out: &mut W,
+) -> Result<BatchError> {
+    let mut failures = BatchError::default();
+    
+    for (index, line) in lines.into_iter().enumerate() {
+        let line = line.trim();
+        info!("Processing input: {}", line);
+        failures.total += 1;
+        
+        let result = process_data_detailed(line, &ProcessOptions::default());
+        if let Err(e) = &result {
+            e.log_with("Processing failed");
+            if !continue_on_error {
+                return result.map(|_| failures);
+            }
+        }
+        
+        match (output, &result) {
+            (OutputFormat::Text, Ok(result)) => writeln!(out, "{}", result.output)?,
+            (OutputFormat::Text, Err(_)) => {}
+            (OutputFormat::Json, Ok(result)) => {
+                let mut record = serde_json::to_value(result)?;
+                record["input"] = line.into();
+                record["status"] = "ok".into();
+                writeln!(out, "{}", record)?;
+            }
+            (OutputFormat::Json, Err(e)) => {
+                let record = serde_json::json!({
+                    "input": line,
+                    "error": e.to_string(),
+                    "code": e.code(),
+                    "status": "error",
+                });
+                writeln!(out, "{}", record)?;
+            }
+        }
+        
+        if let Err(e) = result {
+            failures.push(index, e);
         }
     }
+    
+    Ok(failures)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn run(lines: &[&str], output: OutputFormat, continue_on_error: bool) -> (Result<BatchError>, String) {
+        let mut out = Vec::new();
+        let result = process_lines(lines.iter().copied(), output, continue_on_error, &mut out);
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_process_lines_text() {
+        let (result, out) = run(&["hello", "world"], OutputFormat::Text, false);
+        assert!(result.unwrap().is_empty());
+        assert_eq!(out, "Processed: HELLO\nProcessed: WORLD\n");
+    }
+
     #[test]
-    fn test_parse_request_line() {
-        let request = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
-        let (method, path) = parse_request_line(request).unwrap();
-        assert_eq!(method, "GET");
-        assert_eq!(path, "/");
+    fn test_process_lines_json() {
+        let (result, out) = run(&["hello", "world"], OutputFormat::Json, false);
+        assert!(result.unwrap().is_empty());
+
+        let records: Vec<serde_json::Value> = out
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["input"], "hello");
+        assert_eq!(records[0]["output"], "Processed: HELLO");
+        assert_eq!(records[0]["status"], "ok");
+        assert_eq!(records[0]["input_len"], 5);
+        assert_eq!(records[0]["truncated"], false);
+        assert!(records[0]["duration_us"].is_u64());
+        assert_eq!(records[1]["input"], "world");
     }
 
     #[test]
-    fn test_extract_body() {
-        let request = "POST /process HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
-        let body = extract_body(request);
-        assert_eq!(body, "hello");
+    fn test_process_lines_continue_on_error() {
+        let (result, out) = run(&["hello", "", "world"], OutputFormat::Json, true);
+        assert_eq!(result.unwrap().to_string(), "1 of 3 items failed (indices 1)");
+
+        let records: Vec<serde_json::Value> = out
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1]["status"], "error");
+        assert_eq!(records[1]["code"], "INVALID_INPUT");
+        assert_eq!(records[2]["output"], "Processed: WORLD");
     }
 
     #[test]
-    fn test_create_response() {
-        let response = create_response(200, "OK", "text/plain", "Hello");
-        assert!(response.contains("HTTP/1.1 200 OK"));
-        assert!(response.contains("Content-Type: text/plain"));
-        assert!(response.contains("Content-Length: 5"));
-        assert!(response.contains("Hello"));
+    fn test_process_lines_aborts_on_error() {
+        let (result, out) = run(&["hello", "", "world"], OutputFormat::Json, false);
+        assert!(result.is_err());
+        assert_eq!(out.lines().count(), 1);
+    }
+
+    async fn run_inputs(
+        inputs: &[(&'static str, Option<&'static str>)],
+        concurrency: usize,
+        continue_on_error: bool,
+    ) -> (Result<usize>, String) {
+        let read = |(name, data): &(&str, Option<&str>)| {
+            data.map(str::to_string).ok_or_else(|| Error::NotFound(name.to_string()))
+        };
+        let mut out = Vec::new();
+        let result =
+            process_inputs(inputs.to_vec(), read, concurrency, OutputFormat::Text, continue_on_error, &mut out).await;
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_process_inputs_concurrently_in_order() {
+        let inputs = [
+            ("a", Some("one\ntwo")),
+            ("b", Some("three")),
+            ("c", Some("four\nfive\nsix")),
+            ("d", Some("seven")),
+        ];
+        let (result, out) = run_inputs(&inputs, 3, false).await;
+        assert_eq!(result.unwrap(), 0);
+        assert_eq!(
+            out,
+            "Processed: ONE\nProcessed: TWO\nProcessed: THREE\nProcessed: FOUR\n\
+             Processed: FIVE\nProcessed: SIX\nProcessed: SEVEN\n"
+        );
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_process_inputs_continue_on_error() {
+        let inputs = [("a", Some("one")), ("missing", None), ("c", Some("two\n\nthree"))];
+        let (result, out) = run_inputs(&inputs, 2, true).await;
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(out, "Processed: ONE\nProcessed: TWO\nProcessed: THREE\n");
+
+        let (result, out) = run_inputs(&inputs, 2, false).await;
+        assert!(matches!(result, Err(Error::NotFound(_))));
+        assert_eq!(out, "Processed: ONE\n");
+    }
+
+    fn report(outcomes: &[CheckOutcome]) -> (usize, String) {
+        let mut out = Vec::new();
+        let failed = write_check_report(outcomes, &mut out).unwrap();
+        (failed, String::from_utf8(out).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_startup_checks_pass() {
+        let outcomes = startup_checks(&Config::default()).await;
+        let (failed, out) = report(&outcomes);
+
+        assert_eq!(failed, 0, "{}", out);
+        assert_eq!(out.lines().filter(|line| line.starts_with("PASS")).count(), outcomes.len());
+    }
+
+    #[tokio::test]
+    async fn test_startup_checks_fail_on_unreadable_tls_files() {
+        let cert = tempfile::NamedTempFile::new().unwrap();
+        let mut config = Config::default();
+        config.server.tls_enabled = true;
+        config.server.tls_cert_path = Some(cert.path().to_path_buf());
+        config.server.tls_key_path = Some("/nonexistent/server.key".into());
+
+        let (failed, out) = report(&startup_checks(&config).await);
+        assert_eq!(failed, 1);
+        assert!(out.contains("FAIL  tls files: Configuration error: Cannot read TLS private key"));
+
+        config.server.tls_key_path = None;
+        let (failed, out) = report(&startup_checks(&config).await);
+        assert_eq!(failed, 1);
+        assert!(out.contains("no private key path is set"));
+    }
+}
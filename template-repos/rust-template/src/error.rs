@@ -6,6 +6,9 @@ pub enum Error {
     #[error("Invalid input: {0}")]
     InvalidInput(String),
     
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+    
     #[error("Configuration error: {0}")]
     Config(String),
     
@@ -32,6 +35,21 @@ pub enum Error {
     
     #[error("Internal server error: {0}")]
     Internal(String),
+    
+    #[error("Request line too long: {0}")]
+    UriTooLong(String),
+    
+    #[error("Request header too large: {0}")]
+    HeaderTooLarge(String),
+    
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
+    
+    #[error("Operation timed out: {0}")]
+    Timeout(String),
+    
+    #[error("Not implemented: {0}")]
+    NotImplemented(String),
 }
 
 /// Application result type.
@@ -42,20 +60,140 @@ impl Error {
     pub fn is_recoverable(&self) -> bool {
         matches!(
             self,
-            Error::Network(_) | Error::Database(_) | Error::Io(_)
+            Error::Network(_) | Error::Database(_) | Error::Io(_) | Error::Timeout(_)
         )
     }
     
     /// Get error severity level.
     pub fn severity(&self) -> ErrorSeverity {
         match self {
-            Error::InvalidInput(_) | Error::Config(_) => ErrorSeverity::Warning,
+            Error::InvalidInput(_)
+            | Error::BadRequest(_)
+            | Error::UriTooLong(_)
+            | Error::HeaderTooLarge(_)
+            | Error::PayloadTooLarge(_)
+            | Error::NotImplemented(_)
+            | Error::Config(_)
+            | Error::Timeout(_) => {
+                ErrorSeverity::Warning
+            }
             Error::Auth(_) | Error::Permission(_) => ErrorSeverity::Error,
             Error::NotFound(_) => ErrorSeverity::Info,
             Error::Network(_) | Error::Database(_) | Error::Io(_) => ErrorSeverity::Error,
             Error::Serialization(_) | Error::Internal(_) => ErrorSeverity::Critical,
         }
     }
+    
+    /// Get the HTTP status code this error maps to.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            Error::InvalidInput(_) | Error::BadRequest(_) => 400,
+            Error::Auth(_) => 401,
+            Error::Permission(_) => 403,
+            Error::NotFound(_) => 404,
+            Error::PayloadTooLarge(_) => 413,
+            Error::UriTooLong(_) => 414,
+            Error::HeaderTooLarge(_) => 431,
+            Error::NotImplemented(_) => 501,
+            Error::Network(_) => 502,
+            Error::Database(_) => 503,
+            Error::Timeout(_) => 504,
+            Error::Config(_) | Error::Io(_) | Error::Serialization(_) | Error::Internal(_) => 500,
+        }
+    }
+    
+    /// Get a stable, machine-readable error code.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::InvalidInput(_) => "INVALID_INPUT",
+            Error::BadRequest(_) => "BAD_REQUEST",
+            Error::UriTooLong(_) => "URI_TOO_LONG",
+            Error::HeaderTooLarge(_) => "HEADER_TOO_LARGE",
+            Error::PayloadTooLarge(_) => "PAYLOAD_TOO_LARGE",
+            Error::Config(_) => "CONFIG_ERROR",
+            Error::Io(_) => "IO_ERROR",
+            Error::Serialization(_) => "SERIALIZATION_ERROR",
+            Error::Network(_) => "NETWORK_ERROR",
+            Error::Database(_) => "DATABASE_ERROR",
+            Error::Auth(_) => "AUTH_ERROR",
+            Error::Permission(_) => "PERMISSION_DENIED",
+            Error::NotFound(_) => "NOT_FOUND",
+            Error::Internal(_) => "INTERNAL_ERROR",
+            Error::Timeout(_) => "TIMEOUT",
+            Error::NotImplemented(_) => "NOT_IMPLEMENTED",
+        }
+    }
+    
+    /// Prefix the error's message with some context, keeping its variant.
+    ///
+    /// `Timeout("upstream")` with context `"Fetching user"` displays as
+    /// `Operation timed out: Fetching user: upstream`, and its code, status and
+    /// recoverability are unchanged.
+    pub fn context(self, context: &str) -> Error {
+        let add = |message: String| format!("{}: {}", context, message);
+        match self {
+            Error::InvalidInput(message) => Error::InvalidInput(add(message)),
+            Error::BadRequest(message) => Error::BadRequest(add(message)),
+            Error::Config(message) => Error::Config(add(message)),
+            Error::Io(e) => Error::Io(std::io::Error::new(e.kind(), add(e.to_string()))),
+            Error::Serialization(e) => Error::Serialization(serde::de::Error::custom(add(e.to_string()))),
+            Error::Network(message) => Error::Network(add(message)),
+            Error::Database(message) => Error::Database(add(message)),
+            Error::Auth(message) => Error::Auth(add(message)),
+            Error::Permission(message) => Error::Permission(add(message)),
+            Error::NotFound(message) => Error::NotFound(add(message)),
+            Error::Internal(message) => Error::Internal(add(message)),
+            Error::UriTooLong(message) => Error::UriTooLong(add(message)),
+            Error::HeaderTooLarge(message) => Error::HeaderTooLarge(add(message)),
+            Error::PayloadTooLarge(message) => Error::PayloadTooLarge(add(message)),
+            Error::Timeout(message) => Error::Timeout(add(message)),
+            Error::NotImplemented(message) => Error::NotImplemented(add(message)),
+        }
+    }
+    
+    /// Log the error at the tracing level matching its severity.
+    pub fn log(&self) {
+        self.emit(&self.to_string());
+    }
+    
+    /// Log the error prefixed with some context.
+    pub fn log_with(&self, context: &str) {
+        self.emit(&format!("{}: {}", context, self));
+    }
+    
+    fn emit(&self, message: &str) {
+        let code = self.code();
+        match self.severity() {
+            ErrorSeverity::Critical | ErrorSeverity::Error => tracing::error!(code, "{}", message),
+            ErrorSeverity::Warning => tracing::warn!(code, "{}", message),
+            ErrorSeverity::Info => tracing::info!(code, "{}", message),
+        }
+    }
+}
+
+/// Io and Serialization errors are cloned as new errors of the same kind
+/// carrying the same message; every other variant clones exactly.
+impl Clone for Error {
+    fn clone(&self) -> Self {
+        match self {
+            Error::InvalidInput(message) => Error::InvalidInput(message.clone()),
+            Error::BadRequest(message) => Error::BadRequest(message.clone()),
+            Error::Config(message) => Error::Config(message.clone()),
+            Error::Io(e) => Error::Io(std::io::Error::new(e.kind(), e.to_string())),
+            Error::Serialization(e) => Error::Serialization(serde::de::Error::custom(e.to_string())),
+            Error::Network(message) => Error::Network(message.clone()),
+            Error::Database(message) => Error::Database(message.clone()),
+            Error::Auth(message) => Error::Auth(message.clone()),
+            Error::Permission(message) => Error::Permission(message.clone()),
+            Error::NotFound(message) => Error::NotFound(message.clone()),
+            Error::Internal(message) => Error::Internal(message.clone()),
+            Error::UriTooLong(message) => Error::UriTooLong(message.clone()),
+            Error::HeaderTooLarge(message) => Error::HeaderTooLarge(message.clone()),
+            Error::PayloadTooLarge(message) => Error::PayloadTooLarge(message.clone()),
+            Error::Timeout(message) => Error::Timeout(message.clone()),
+            Error::NotImplemented(message) => Error::NotImplemented(message.clone()),
+        }
+    }
 }
 
 /// Error severity levels.
@@ -78,6 +216,63 @@ impl std::fmt::Display for ErrorSeverity {
     }
 }
 
+/// Per-item failures collected while processing a batch.
+///
+/// Displays as a summary such as `2 of 5 items failed (indices 1, 3)`.
+#[derive(Debug, Default)]
+pub struct BatchError {
+    /// Number of items in the batch
+    pub total: usize,
+    /// Index of each failed item with its error, in the order they failed
+    pub errors: Vec<(usize, Error)>,
+}
+
+impl BatchError {
+    /// Create an empty batch error for `total` items.
+    pub fn new(total: usize) -> Self {
+        Self { total, errors: Vec::new() }
+    }
+
+    /// Record a failure of the item at `index`.
+    pub fn push(&mut self, index: usize, error: Error) {
+        self.errors.push((index, error));
+    }
+
+    /// Number of failed items.
+    pub fn len(&self) -> usize {
+        self.errors.len()
+    }
+
+    /// Whether no item failed.
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Indices of the failed items.
+    pub fn indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.errors.iter().map(|(index, _)| *index)
+    }
+}
+
+impl std::fmt::Display for BatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} of {} items failed", self.errors.len(), self.total)?;
+        for (i, index) in self.indices().enumerate() {
+            write!(f, "{}{}", if i == 0 { " (indices " } else { ", " }, index)?;
+        }
+        if !self.errors.is_empty() {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.errors.first().map(|(_, error)| error as _)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -87,6 +282,10 @@ mod tests {
         let error = Error::InvalidInput("test".to_string());
         assert_eq!(error.severity(), ErrorSeverity::Warning);
         
+        let error = Error::BadRequest("test".to_string());
+        assert_eq!(error.severity(), ErrorSeverity::Warning);
+        assert_ne!(error.code(), Error::InvalidInput("test".to_string()).code());
+        
         let error = Error::Internal("test".to_string());
         assert_eq!(error.severity(), ErrorSeverity::Critical);
     }
@@ -99,4 +298,98 @@ mod tests {
         let error = Error::Auth("test".to_string());
         assert!(!error.is_recoverable());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_timeout_error() {
+        let error = Error::Timeout("request took too long".to_string());
+        assert_eq!(error.severity(), ErrorSeverity::Warning);
+        assert!(error.is_recoverable());
+        assert_eq!(error.status_code(), 504);
+        assert_eq!(error.code(), "TIMEOUT");
+        assert_ne!(error.code(), Error::Network("test".to_string()).code());
+    }
+
+    #[test]
+    fn test_error_log_uses_severity() {
+        let (logs, _guard) = crate::testing::LogCapture::install();
+        
+        Error::Timeout("upstream".to_string()).log_with("Fetching data");
+        Error::NotFound("user".to_string()).log();
+        
+        let contents = logs.contents();
+        let timeout = contents.lines().find(|line| line.contains("Fetching data")).unwrap();
+        assert!(timeout.contains("WARN"));
+        assert!(timeout.contains("code=\"TIMEOUT\""));
+        assert!(timeout.contains("Fetching data: Operation timed out: upstream"));
+        
+        let not_found = contents.lines().find(|line| line.contains("user")).unwrap();
+        assert!(not_found.contains("INFO"));
+        assert!(not_found.contains("code=\"NOT_FOUND\""));
+    }
+
+    #[test]
+    fn test_error_context_keeps_variant() {
+        let error = Error::Timeout("upstream".to_string()).context("Fetching user");
+        assert_eq!(error.to_string(), "Operation timed out: Fetching user: upstream");
+        assert!(matches!(error, Error::Timeout(_)));
+        assert!(error.is_recoverable());
+        assert_eq!(error.code(), "TIMEOUT");
+        
+        let error = Error::Auth("bad token".to_string()).context("Login").context("Request 42");
+        assert_eq!(error.to_string(), "Authentication error: Request 42: Login: bad token");
+        assert!(!error.is_recoverable());
+        
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        let error = Error::from(io).context("Reading config.json");
+        assert!(matches!(&error, Error::Io(e) if e.kind() == std::io::ErrorKind::NotFound));
+        assert!(error.to_string().contains("Reading config.json: no such file"));
+        assert!(error.is_recoverable());
+        
+        let json = serde_json::from_str::<u32>("x").unwrap_err();
+        let error = Error::from(json).context("Parsing body");
+        assert_eq!(error.code(), "SERIALIZATION_ERROR");
+        assert!(error.to_string().starts_with("Serialization error: Parsing body: "));
+    }
+
+    #[test]
+    fn test_error_status_codes() {
+        assert_eq!(Error::InvalidInput("test".to_string()).status_code(), 400);
+        assert_eq!(Error::BadRequest("test".to_string()).status_code(), 400);
+        assert_eq!(Error::Auth("test".to_string()).status_code(), 401);
+        assert_eq!(Error::NotFound("test".to_string()).status_code(), 404);
+        assert_eq!(Error::PayloadTooLarge("test".to_string()).status_code(), 413);
+        assert_eq!(Error::HeaderTooLarge("test".to_string()).status_code(), 431);
+        assert_eq!(Error::Internal("test".to_string()).status_code(), 500);
+        assert_eq!(Error::NotImplemented("test".to_string()).status_code(), 501);
+    }
+
+    #[test]
+    fn test_batch_error() {
+        let mut batch = BatchError::new(5);
+        assert!(batch.is_empty());
+        assert_eq!(batch.to_string(), "0 of 5 items failed");
+
+        batch.push(1, Error::InvalidInput("empty".to_string()));
+        batch.push(3, Error::NotFound("user".to_string()));
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch.indices().collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(batch.to_string(), "2 of 5 items failed (indices 1, 3)");
+
+        let error: &dyn std::error::Error = &batch;
+        assert_eq!(error.source().unwrap().to_string(), "Invalid input: empty");
+    }
+
+    #[test]
+    fn test_error_clone() {
+        let error = Error::Timeout("upstream".to_string());
+        assert!(matches!(error.clone(), Error::Timeout(message) if message == "upstream"));
+
+        let io = Error::from(std::io::Error::new(std::io::ErrorKind::NotFound, "no such file"));
+        let cloned = io.clone();
+        assert!(matches!(&cloned, Error::Io(e) if e.kind() == std::io::ErrorKind::NotFound));
+        assert_eq!(cloned.to_string(), io.to_string());
+
+        let json = Error::from(serde_json::from_str::<u32>("x").unwrap_err());
+        assert_eq!(json.clone().to_string(), json.to_string());
+    }
+}
@@ -0,0 +1,224 @@
+//! Reusable health checks for registering with [`HealthChecker`](crate::utils::HealthChecker).
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+use crate::utils::generate_random_string;
+
+/// Outcome of one health check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    /// The check ran and succeeded
+    Passed,
+    /// The check ran and failed
+    Failed,
+    /// The check didn't run because a dependency didn't pass
+    Skipped,
+}
+
+/// Overall health derived from a [`HealthReport`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// Every check passed
+    Healthy,
+    /// No check failed, but some were skipped
+    Degraded,
+    /// At least one check failed
+    Unhealthy,
+}
+
+impl HealthStatus {
+    /// Compact plain-text form: `OK`, `DEGRADED` or `UNHEALTHY`.
+    pub fn as_text(&self) -> &'static str {
+        match self {
+            HealthStatus::Healthy => "OK",
+            HealthStatus::Degraded => "DEGRADED",
+            HealthStatus::Unhealthy => "UNHEALTHY",
+        }
+    }
+}
+
+/// How much a check counts towards [`HealthReport::score`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Criticality {
+    /// Any outcome but a pass forces the score to zero
+    #[default]
+    Critical,
+    /// Counts towards the score in proportion to its weight
+    Optional(f64),
+}
+
+/// Result of one named health check.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CheckResult {
+    /// Check name
+    pub name: String,
+
+    /// Outcome
+    pub status: CheckStatus,
+
+    /// Failure message, or why the check was skipped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+
+    /// How much the check counts towards the score
+    pub criticality: Criticality,
+}
+
+/// Results of every registered health check, in the order they ran.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct HealthReport {
+    /// Per-check results
+    pub checks: Vec<CheckResult>,
+}
+
+impl HealthReport {
+    /// Check if every check passed.
+    pub fn is_healthy(&self) -> bool {
+        self.checks.iter().all(|check| check.status == CheckStatus::Passed)
+    }
+
+    /// Overall status of the report.
+    pub fn status(&self) -> HealthStatus {
+        if self.with_status(CheckStatus::Failed).next().is_some() {
+            HealthStatus::Unhealthy
+        } else if self.is_healthy() {
+            HealthStatus::Healthy
+        } else {
+            HealthStatus::Degraded
+        }
+    }
+
+    /// Health as a number from 0.0 to 1.0.
+    ///
+    /// This is the weighted fraction of optional checks that passed, or 1.0
+    /// if there are none. A critical check that failed or was skipped makes
+    /// the score 0.0.
+    pub fn score(&self) -> f64 {
+        let (mut passed, mut total) = (0.0, 0.0);
+        for check in &self.checks {
+            let pass = check.status == CheckStatus::Passed;
+            match check.criticality {
+                Criticality::Critical if !pass => return 0.0,
+                Criticality::Critical => {}
+                Criticality::Optional(weight) => {
+                    let weight = weight.max(0.0);
+                    total += weight;
+                    if pass {
+                        passed += weight;
+                    }
+                }
+            }
+        }
+
+        if total > 0.0 {
+            passed / total
+        } else {
+            1.0
+        }
+    }
+
+    /// Look up a check's result by name.
+    pub fn get(&self, name: &str) -> Option<&CheckResult> {
+        self.checks.iter().find(|check| check.name == name)
+    }
+
+    /// Results of checks with the given status.
+    pub fn with_status(&self, status: CheckStatus) -> impl Iterator<Item = &CheckResult> {
+        self.checks.iter().filter(move |check| check.status == status)
+    }
+}
+
+/// Check that files can be created in a directory.
+///
+/// Writes and removes a small probe file, so a read-only or full filesystem
+/// shows up as unhealthy instead of silently breaking whatever writes there.
+pub fn check_writable(path: &Path) -> Result<()> {
+    let probe = path.join(format!(".health-probe-{}", generate_random_string(12)));
+
+    std::fs::write(&probe, b"ok").map_err(|e| {
+        Error::Io(std::io::Error::new(
+            e.kind(),
+            format!("{} is not writable: {}", path.display(), e),
+        ))
+    })?;
+    std::fs::remove_file(&probe)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_writable() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        check_writable(dir.path())?;
+
+        // The probe file is cleaned up
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_writable_fails() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+
+        let missing = dir.path().join("missing");
+        assert!(matches!(check_writable(&missing), Err(Error::Io(_))));
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"")?;
+        assert!(matches!(check_writable(&file), Err(Error::Io(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_status() {
+        let result = |name: &str, status| CheckResult {
+            name: name.to_string(),
+            status,
+            detail: None,
+            criticality: Criticality::Critical,
+        };
+        let mut report = HealthReport { checks: vec![result("disk", CheckStatus::Passed)] };
+        assert_eq!(report.status(), HealthStatus::Healthy);
+
+        report.checks.push(result("cache", CheckStatus::Skipped));
+        assert_eq!(report.status(), HealthStatus::Degraded);
+        assert_eq!(report.status().as_text(), "DEGRADED");
+
+        report.checks.push(result("database", CheckStatus::Failed));
+        assert_eq!(report.status(), HealthStatus::Unhealthy);
+    }
+
+    #[test]
+    fn test_report_score() {
+        let result = |status, criticality| CheckResult { name: String::new(), status, detail: None, criticality };
+        let mut report = HealthReport {
+            checks: vec![
+                result(CheckStatus::Passed, Criticality::Critical),
+                result(CheckStatus::Passed, Criticality::Optional(3.0)),
+                result(CheckStatus::Passed, Criticality::Optional(1.0)),
+            ],
+        };
+        assert_eq!(report.score(), 1.0);
+        assert_eq!(HealthReport::default().score(), 1.0);
+
+        report.checks[2].status = CheckStatus::Failed;
+        assert_eq!(report.score(), 0.75);
+
+        report.checks[1].status = CheckStatus::Skipped;
+        assert_eq!(report.score(), 0.0);
+        report.checks[1].status = CheckStatus::Passed;
+
+        report.checks[0].status = CheckStatus::Failed;
+        assert_eq!(report.score(), 0.0);
+    }
+}
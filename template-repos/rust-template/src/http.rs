@@ -0,0 +1,1733 @@
+//! HTTP request and response types used by the server.
+
+use std::net::SocketAddr;
+
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+use tokio::sync::{mpsc, watch};
+
+use crate::config::ServerConfig;
+use crate::error::{Error, Result};
+
+/// Media type of newline-delimited JSON, whose request bodies are streamed.
+pub const NDJSON: &str = "application/x-ndjson";
+
+/// Protocol versions the server speaks.
+const HTTP_10: &str = "HTTP/1.0";
+const HTTP_11: &str = "HTTP/1.1";
+
+/// Longest chunk-size or trailer line accepted in a chunked body.
+const MAX_CHUNK_LINE_BYTES: usize = 1024;
+
+/// Case-insensitive, order-preserving collection of HTTP headers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    /// Create an empty header map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the first value for a header.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Get every value for a header, in insertion order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Check whether a header is present.
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Set a header, replacing any existing values.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.entries.push((name, value.into()));
+    }
+
+    /// Add a header value, keeping any existing values.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    /// Remove every value for a header.
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    /// Iterate over all header entries.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Number of header entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if there are no headers.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// A parsed HTTP request.
+#[derive(Debug)]
+pub struct Request {
+    /// Request method
+    pub method: String,
+
+    /// Request path
+    pub path: String,
+
+    /// Protocol version (e.g. `HTTP/1.1`)
+    pub version: String,
+
+    /// Request headers
+    pub headers: HeaderMap,
+
+    /// Request body, exactly as received
+    pub body: Vec<u8>,
+
+    /// Fires when the server starts shutting down
+    pub shutdown: ShutdownSignal,
+
+    /// Address of the client the request came from, when known
+    pub peer: Option<SocketAddr>,
+
+    /// Body chunks still arriving, for bodies that are streamed
+    pub body_stream: BodyStream,
+}
+
+/// A request body delivered in chunks as it arrives.
+///
+/// The server streams [`NDJSON`] bodies this way instead of reading them
+/// into [`Request::body`] before the handler runs.
+#[derive(Debug, Default)]
+pub struct BodyStream {
+    receiver: Option<mpsc::Receiver<Result<Vec<u8>>>>,
+}
+
+impl BodyStream {
+    pub(crate) fn new(receiver: mpsc::Receiver<Result<Vec<u8>>>) -> Self {
+        Self { receiver: Some(receiver) }
+    }
+
+    /// Wait for the next chunk, or `None` once the whole body has been read.
+    pub async fn next_chunk(&mut self) -> Option<Result<Vec<u8>>> {
+        self.receiver.as_mut()?.recv().await
+    }
+}
+
+/// Notice that the server is shutting down, for long-running handlers.
+///
+/// A streaming handler can `select!` on [`ShutdownSignal::recv`] to finish
+/// its response cleanly instead of being cut off when the grace period ends.
+/// The default signal, used by requests built outside the server, never fires.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownSignal {
+    receiver: Option<watch::Receiver<bool>>,
+}
+
+impl ShutdownSignal {
+    /// Follow a channel that is set to `true` when shutdown starts.
+    pub(crate) fn new(receiver: watch::Receiver<bool>) -> Self {
+        Self { receiver: Some(receiver) }
+    }
+
+    /// Check if shutdown has started.
+    pub fn is_shutting_down(&self) -> bool {
+        self.receiver.as_ref().is_some_and(|receiver| *receiver.borrow())
+    }
+
+    /// Wait until shutdown starts.
+    ///
+    /// Also returns if the server has gone away without signalling.
+    pub async fn recv(&self) {
+        match &self.receiver {
+            Some(receiver) => {
+                let _ = receiver.clone().wait_for(|&stopping| stopping).await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+}
+
+impl Request {
+    /// Create a request with no headers or body.
+    pub fn new(method: impl Into<String>, path: impl Into<String>) -> Self {
+        Self {
+            method: method.into(),
+            path: path.into(),
+            version: HTTP_11.to_string(),
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+            shutdown: ShutdownSignal::default(),
+            peer: None,
+            body_stream: BodyStream::default(),
+        }
+    }
+
+    /// Parse a raw HTTP request.
+    pub fn parse(raw: &str) -> Result<Self> {
+        let (method, path) = parse_request_line(raw)?;
+        let version = raw
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(2))
+            .unwrap_or(HTTP_11)
+            .to_string();
+
+        let head = match raw.find("\r\n\r\n").or_else(|| raw.find("\n\n")) {
+            Some(end) => &raw[..end],
+            None => raw,
+        };
+
+        Ok(Self {
+            method,
+            path,
+            version,
+            headers: parse_headers(head.lines().skip(1)),
+            body: extract_body(raw).into_bytes(),
+            shutdown: ShutdownSignal::default(),
+            peer: None,
+            body_stream: BodyStream::default(),
+        })
+    }
+
+    /// The raw body bytes.
+    pub fn body_bytes(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// The body as text, failing if it is not valid UTF-8.
+    pub fn body_str(&self) -> Result<&str> {
+        std::str::from_utf8(&self.body)
+            .map_err(|e| Error::BadRequest(format!("Request body is not valid UTF-8: {}", e)))
+    }
+
+    /// Take the body as a stream of chunks, whether it was streamed or buffered.
+    pub fn take_body_stream(&mut self) -> BodyStream {
+        let stream = std::mem::take(&mut self.body_stream);
+        if stream.receiver.is_some() {
+            return stream;
+        }
+
+        let (sender, receiver) = mpsc::channel(1);
+        if !self.body.is_empty() {
+            let _ = sender.try_send(Ok(std::mem::take(&mut self.body)));
+        }
+        BodyStream::new(receiver)
+    }
+
+    /// Whether the server streams this request's body instead of buffering it.
+    pub fn streams_body(&self) -> bool {
+        let has_body = self.is_chunked() || self.content_length().is_ok_and(|length| length.unwrap_or(0) > 0);
+        has_body && self.media_type() == NDJSON
+    }
+
+    /// Whether the body is sent with chunked transfer encoding.
+    pub fn is_chunked(&self) -> bool {
+        self.transfer_codings().last().is_some_and(|coding| coding.eq_ignore_ascii_case("chunked"))
+    }
+
+    /// Every transfer coding applied to the body, in order, across all
+    /// `Transfer-Encoding` headers.
+    fn transfer_codings(&self) -> impl Iterator<Item = &str> {
+        self.headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|coding| !coding.is_empty())
+    }
+
+    /// Check that the body's framing is unambiguous and readable.
+    ///
+    /// Requests with both `Transfer-Encoding` and `Content-Length`, or whose
+    /// last transfer coding isn't `chunked`, can't be delimited safely and
+    /// fail with [`Error::BadRequest`]. Codings other than `chunked` can't be
+    /// decoded and fail with [`Error::NotImplemented`].
+    pub fn check_framing(&self) -> Result<()> {
+        if !self.headers.contains("Transfer-Encoding") {
+            return Ok(());
+        }
+        if self.headers.contains("Content-Length") {
+            return Err(Error::BadRequest("Both Transfer-Encoding and Content-Length are set".to_string()));
+        }
+        if !self.is_chunked() {
+            return Err(Error::BadRequest("Transfer-Encoding must end with chunked".to_string()));
+        }
+        match self.transfer_codings().find(|coding| !coding.eq_ignore_ascii_case("chunked")) {
+            Some(coding) => Err(Error::NotImplemented(format!("Transfer coding {:?} is not supported", coding))),
+            None if self.transfer_codings().count() > 1 => {
+                Err(Error::BadRequest("Chunked is applied more than once".to_string()))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// The body length announced by `Content-Length`, if present.
+    ///
+    /// Fails unless every value is a plain non-negative integer and all of
+    /// them agree, whether repeated across headers or in a comma-separated list.
+    pub fn content_length(&self) -> Result<Option<usize>> {
+        let mut length = None;
+        for value in self.headers.get_all("Content-Length").flat_map(|value| value.split(',')) {
+            let value = value.trim();
+            let parsed = match value.bytes().all(|b| b.is_ascii_digit()) {
+                true => value.parse::<usize>().ok(),
+                false => None,
+            };
+            let Some(parsed) = parsed else {
+                return Err(Error::BadRequest(format!("Invalid Content-Length: {:?}", value)));
+            };
+            if length.is_some_and(|length| length != parsed) {
+                return Err(Error::BadRequest("Conflicting Content-Length headers".to_string()));
+            }
+            length = Some(parsed);
+        }
+        Ok(length)
+    }
+
+    /// [`content_length`](Self::content_length), failing with
+    /// [`Error::PayloadTooLarge`] if it announces more than `max_bytes`.
+    pub fn checked_content_length(&self, max_bytes: usize) -> Result<Option<usize>> {
+        match self.content_length()? {
+            Some(length) if length > max_bytes => {
+                Err(Error::PayloadTooLarge(format!("Request body exceeds {} bytes", max_bytes)))
+            }
+            length => Ok(length),
+        }
+    }
+
+    /// Get a request header value.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Whether the client is waiting for `100 Continue` before sending the body.
+    ///
+    /// HTTP/1.0 clients don't understand interim responses, so never are.
+    pub fn expects_continue(&self) -> bool {
+        !self.is_http10()
+            && self.header("Expect")
+                .is_some_and(|value| value.trim().eq_ignore_ascii_case("100-continue"))
+    }
+
+    /// Whether the request was sent with HTTP/1.0.
+    pub fn is_http10(&self) -> bool {
+        self.version.eq_ignore_ascii_case(HTTP_10)
+    }
+
+    /// Whether the client wants the connection kept open after this request.
+    ///
+    /// HTTP/1.1 connections persist unless the client sends `Connection: close`;
+    /// HTTP/1.0 ones close unless it sends `Connection: keep-alive`.
+    pub fn wants_keep_alive(&self) -> bool {
+        let has_option = |option: &str| {
+            self.header("Connection")
+                .is_some_and(|value| value.split(',').any(|token| token.trim().eq_ignore_ascii_case(option)))
+        };
+        if has_option("close") {
+            return false;
+        }
+        !self.is_http10() || has_option("keep-alive")
+    }
+
+    /// Match the `Range` header against a body of `len` bytes.
+    pub fn byte_range(&self, len: u64) -> RangeMatch {
+        RangeMatch::parse(self.header("Range"), len)
+    }
+
+    /// Whether the `Accept` header ranks JSON above HTML.
+    pub fn prefers_json(&self) -> bool {
+        self.accept_quality("application/json") > self.accept_quality("text/html")
+    }
+
+    /// Quality the `Accept` header gives a media type, 0 if it isn't listed.
+    pub fn accept_quality(&self, media_type: &str) -> f32 {
+        let Some(accept) = self.header("Accept") else {
+            return 0.0;
+        };
+
+        accept
+            .split(',')
+            .filter_map(|entry| {
+                let mut parts = entry.split(';');
+                if !parts.next()?.trim().eq_ignore_ascii_case(media_type) {
+                    return None;
+                }
+                let q = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .and_then(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                Some(q)
+            })
+            .fold(0.0f32, f32::max)
+    }
+
+    /// Deserialize an `application/json` body.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        let content_type = self.media_type();
+        if content_type != "application/json" && !content_type.ends_with("+json") {
+            return Err(Error::BadRequest(format!("Expected a JSON body, got {:?}", content_type)));
+        }
+
+        serde_json::from_slice(&self.body)
+            .map_err(|e| Error::BadRequest(format!("Invalid JSON body: {}", e)))
+    }
+
+    /// Decode an `application/x-www-form-urlencoded` body into name/value pairs.
+    pub fn form(&self) -> Result<Vec<(String, String)>> {
+        let content_type = self.media_type();
+        if content_type != "application/x-www-form-urlencoded" {
+            return Err(Error::BadRequest(format!("Expected a form body, got {:?}", content_type)));
+        }
+
+        self.body_str()?
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                Ok((form_decode(name)?, form_decode(value)?))
+            })
+            .collect()
+    }
+
+    /// The lowercased `Content-Type` without parameters, or `""` if absent.
+    fn media_type(&self) -> String {
+        self.header("Content-Type").map(media_type).unwrap_or_default()
+    }
+}
+
+/// An HTTP response.
+#[derive(Debug)]
+pub struct Response {
+    /// Status code
+    pub status: u16,
+
+    /// Response headers (`Content-Length` is added on serialization)
+    pub headers: HeaderMap,
+
+    /// Response body
+    pub body: Vec<u8>,
+
+    /// Length of a body removed by [`Response::into_head`]
+    head_length: Option<usize>,
+
+    /// Body chunks for a response created by [`Response::stream`]
+    stream: Option<mpsc::Receiver<String>>,
+
+    /// Protocol version written in the status line
+    version: &'static str,
+}
+
+impl Response {
+    /// Create a response with the given status, content type and body.
+    pub fn new(status: u16, content_type: &str, body: impl Into<String>) -> Self {
+        Self::bytes(status, content_type, body.into().into_bytes())
+    }
+
+    /// Create a response with a binary body.
+    pub fn bytes(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", content_type);
+
+        Self {
+            status,
+            headers,
+            body: body.into(),
+            head_length: None,
+            stream: None,
+            version: HTTP_11,
+        }
+    }
+
+    /// Create a response whose body is sent with chunked transfer encoding.
+    ///
+    /// Each string received from `receiver` is written as it arrives; the
+    /// body ends once every sender has been dropped.
+    pub fn stream(status: u16, content_type: &str, receiver: mpsc::Receiver<String>) -> Self {
+        let mut response = Self::new(status, content_type, "");
+        response.stream = Some(receiver);
+        response
+    }
+
+    /// Take the body chunks of a streamed response.
+    pub fn take_stream(&mut self) -> Option<mpsc::Receiver<String>> {
+        self.stream.take()
+    }
+
+    /// Whether the body is produced by a stream rather than held in `body`.
+    pub fn is_streamed(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Whether a streamed body is sent with chunked transfer encoding.
+    ///
+    /// HTTP/1.0 has no chunked encoding, so a streamed body is sent as is and
+    /// ends when the connection closes.
+    pub fn is_chunked(&self) -> bool {
+        self.is_streamed() && self.version == HTTP_11
+    }
+
+    /// Reply with the protocol version of a request: `HTTP/1.0` to HTTP/1.0
+    /// clients, `HTTP/1.1` to everyone else.
+    pub fn with_version(mut self, version: &str) -> Self {
+        self.version = if version.eq_ignore_ascii_case(HTTP_10) { HTTP_10 } else { HTTP_11 };
+        self
+    }
+
+    /// Create a `416 Range Not Satisfiable` response for a body of `len` bytes.
+    pub fn range_not_satisfiable(len: u64) -> Self {
+        Self::new(416, "text/plain", "").with_header("Content-Range", format!("bytes */{}", len))
+    }
+
+    /// Create a `206 Partial Content` response holding `range` of `body`.
+    ///
+    /// A range that does not lie within `body` gets a `416` instead.
+    pub fn partial_content(content_type: &str, body: &[u8], range: ByteRange) -> Self {
+        let total = body.len() as u64;
+        let slice = match (usize::try_from(range.start), usize::try_from(range.end)) {
+            (Ok(start), Ok(end)) if !range.is_empty() => body.get(start..=end),
+            _ => None,
+        };
+        match slice {
+            Some(slice) => Self::bytes(206, content_type, slice)
+                .with_header("Content-Range", range.content_range(total)),
+            None => Self::range_not_satisfiable(total),
+        }
+    }
+
+    /// Answer a `Range` request for `body`: the whole body with `200`, the
+    /// requested part with `206`, or `416` when the range lies outside it.
+    pub fn ranged(content_type: &str, body: &[u8], range: RangeMatch) -> Self {
+        let response = match range {
+            RangeMatch::Full => Self::bytes(200, content_type, body),
+            RangeMatch::Partial(range) => Self::partial_content(content_type, body, range),
+            RangeMatch::Unsatisfiable => Self::range_not_satisfiable(body.len() as u64),
+        };
+        response.with_header("Accept-Ranges", "bytes")
+    }
+
+    /// Create a JSON response.
+    pub fn json(status: u16, body: impl Into<String>) -> Self {
+        Self::new(status, "application/json", body)
+    }
+
+    /// Create an HTML response.
+    pub fn html(status: u16, body: impl Into<String>) -> Self {
+        Self::new(status, "text/html", body)
+    }
+
+    /// Add a header to the response.
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Add a header value, keeping any earlier values so each is sent on its
+    /// own line, as `Set-Cookie` requires.
+    pub fn append_header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Append `; charset=utf-8` to a textual `Content-Type` that has no charset.
+    ///
+    /// Binary types such as `application/octet-stream` are left alone.
+    pub fn with_default_charset(mut self) -> Self {
+        if let Some(content_type) = self.headers.get("Content-Type") {
+            if is_textual(content_type) && !content_type.to_ascii_lowercase().contains("charset=") {
+                let content_type = format!("{}; charset=utf-8", content_type);
+                self.headers.insert("Content-Type", content_type);
+            }
+        }
+        self
+    }
+
+    /// Drop the body for a `HEAD` response, keeping its `Content-Length`.
+    pub fn into_head(mut self) -> Self {
+        self.stream = None;
+        if self.head_length.is_none() {
+            self.head_length = Some(self.body.len());
+            self.body.clear();
+        }
+        self
+    }
+
+    /// Serialize the response as text, replacing any invalid UTF-8 in the body.
+    pub fn to_http_string(&self) -> String {
+        String::from_utf8_lossy(&self.to_http_bytes()).into_owned()
+    }
+
+    /// Serialize the response for the wire.
+    pub fn to_http_bytes(&self) -> Vec<u8> {
+        let mut response = format!("{} {} {}\r\n", self.version, self.status, status_text(self.status));
+
+        for (name, value) in self.headers.iter() {
+            response.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        if self.is_chunked() {
+            // The body follows as chunks
+            response.push_str("Transfer-Encoding: chunked\r\n\r\n");
+            return response.into_bytes();
+        }
+        if self.is_streamed() {
+            // The body runs until the connection closes
+            response.push_str("\r\n");
+            return response.into_bytes();
+        }
+
+        let content_length = self.head_length.unwrap_or(self.body.len());
+        response.push_str(&format!("Content-Length: {}\r\n\r\n", content_length));
+        let mut response = response.into_bytes();
+        response.extend_from_slice(&self.body);
+
+        response
+    }
+}
+
+/// Check that a header name is a non-empty HTTP token.
+pub fn is_valid_header_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// An inclusive range of byte offsets requested with a `Range` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    /// Offset of the first byte
+    pub start: u64,
+    /// Offset of the last byte, inclusive
+    pub end: u64,
+}
+
+impl ByteRange {
+    /// Number of bytes in the range.
+    pub fn len(&self) -> u64 {
+        (self.end + 1).saturating_sub(self.start)
+    }
+
+    /// Whether the range covers no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.end < self.start
+    }
+
+    /// `Content-Range` value for this range of a body of `total` bytes.
+    pub fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+/// How a `Range` header applies to a body of known length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeMatch {
+    /// No usable range; send the whole body with `200 OK`
+    Full,
+    /// Send this part with `206 Partial Content`
+    Partial(ByteRange),
+    /// The range lies outside the body; answer `416 Range Not Satisfiable`
+    Unsatisfiable,
+}
+
+impl RangeMatch {
+    /// Match a single `bytes=start-end`, `bytes=start-` or `bytes=-suffix` range.
+    ///
+    /// An absent or malformed header, another unit, or several ranges fall
+    /// back to [`RangeMatch::Full`], as a server may ignore `Range`. The end is
+    /// clamped to the body.
+    pub fn parse(header: Option<&str>, len: u64) -> RangeMatch {
+        let Some(spec) = header.and_then(|value| value.trim().strip_prefix("bytes=")) else {
+            return RangeMatch::Full;
+        };
+        let Some((start, end)) = spec.split_once('-') else {
+            return RangeMatch::Full;
+        };
+        let (start, end) = (start.trim(), end.trim());
+
+        let range = if start.is_empty() {
+            // The last `suffix` bytes
+            let Ok(suffix) = end.parse::<u64>() else {
+                return RangeMatch::Full;
+            };
+            if suffix == 0 || len == 0 {
+                return RangeMatch::Unsatisfiable;
+            }
+            ByteRange { start: len.saturating_sub(suffix), end: len - 1 }
+        } else {
+            let Ok(start) = start.parse::<u64>() else {
+                return RangeMatch::Full;
+            };
+            let end = match end {
+                "" => None,
+                end => match end.parse::<u64>() {
+                    Ok(end) if end >= start => Some(end),
+                    _ => return RangeMatch::Full,
+                },
+            };
+            if start >= len {
+                return RangeMatch::Unsatisfiable;
+            }
+            ByteRange { start, end: end.map_or(len - 1, |end| end.min(len - 1)) }
+        };
+        RangeMatch::Partial(range)
+    }
+}
+
+/// Whether a media type carries text that needs a charset to decode.
+fn is_textual(content_type: &str) -> bool {
+    let media_type = media_type(content_type);
+    media_type.starts_with("text/")
+        || media_type == "application/json"
+        || media_type == "application/javascript"
+}
+
+/// Lowercased media type of a `Content-Type` value, without parameters.
+fn media_type(content_type: &str) -> String {
+    content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase()
+}
+
+/// Percent-decode a form component, treating `+` as a space.
+fn form_decode(component: &str) -> Result<String> {
+    let invalid = || Error::BadRequest(format!("Invalid percent-encoding: {}", component));
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3).ok_or_else(invalid)?;
+                let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+                decoded.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+                i += 2;
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8(decoded).map_err(|_| invalid())
+}
+
+/// W3C trace context carried in the `traceparent` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    /// 32 hex digit trace id shared by every span in the trace
+    pub trace_id: String,
+
+    /// 16 hex digit id of the current span
+    pub span_id: String,
+
+    /// Trace flags (bit 0 = sampled)
+    pub flags: u8,
+}
+
+impl TraceContext {
+    /// Start a new trace with random ids.
+    pub fn generate() -> Self {
+        Self {
+            trace_id: format!("{:032x}", rand::random::<u128>().max(1)),
+            span_id: random_span_id(),
+            flags: 1,
+        }
+    }
+
+    /// Parse a `traceparent` header value.
+    pub fn parse(value: &str) -> Option<Self> {
+        let parts: Vec<&str> = value.trim().split('-').collect();
+        let [version, trace_id, span_id, flags] = parts[..] else {
+            return None;
+        };
+
+        let is_hex = |s: &str, len: usize| {
+            s.len() == len && s.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        };
+        let is_zero = |s: &str| s.bytes().all(|b| b == b'0');
+
+        if !is_hex(version, 2) || version == "ff" || !is_hex(flags, 2) {
+            return None;
+        }
+        if !is_hex(trace_id, 32) || is_zero(trace_id) || !is_hex(span_id, 16) || is_zero(span_id) {
+            return None;
+        }
+
+        Some(Self {
+            trace_id: trace_id.to_string(),
+            span_id: span_id.to_string(),
+            flags: u8::from_str_radix(flags, 16).ok()?,
+        })
+    }
+
+    /// Continue the trace from a request, or start a new one if the
+    /// `traceparent` header is missing or malformed.
+    pub fn from_request(request: &Request) -> Self {
+        match request.header("traceparent").and_then(Self::parse) {
+            Some(parent) => parent.child(),
+            None => Self::generate(),
+        }
+    }
+
+    /// Create a child span in the same trace.
+    pub fn child(&self) -> Self {
+        Self {
+            trace_id: self.trace_id.clone(),
+            span_id: random_span_id(),
+            flags: self.flags,
+        }
+    }
+
+    /// Format as a `traceparent` header value.
+    pub fn to_header(&self) -> String {
+        format!("00-{}-{}-{:02x}", self.trace_id, self.span_id, self.flags)
+    }
+}
+
+fn random_span_id() -> String {
+    format!("{:016x}", rand::random::<u64>().max(1))
+}
+
+/// Bounds applied while reading a request off the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLimits {
+    /// Longest request line accepted, excluding the trailing CRLF
+    pub max_request_line_bytes: usize,
+
+    /// Longest header name accepted
+    pub max_header_name_bytes: usize,
+
+    /// Longest header value accepted, excluding surrounding whitespace
+    pub max_header_value_bytes: usize,
+
+    /// Most header lines accepted
+    pub max_header_count: usize,
+
+    /// Largest total size of the header lines, including their CRLFs
+    pub max_header_bytes: usize,
+
+    /// Largest body read into memory
+    pub max_body_bytes: usize,
+}
+
+impl RequestLimits {
+    /// Take the limits from the server configuration.
+    pub fn from_config(config: &ServerConfig) -> Self {
+        Self {
+            max_request_line_bytes: config.max_request_line_bytes,
+            max_header_name_bytes: config.max_header_name_bytes,
+            max_header_value_bytes: config.max_header_value_bytes,
+            max_header_count: config.max_header_count,
+            max_header_bytes: config.max_header_bytes,
+            max_body_bytes: config.max_body_bytes,
+        }
+    }
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self::from_config(&ServerConfig::default())
+    }
+}
+
+/// Read a single request from a buffered stream.
+///
+/// Returns `Ok(None)` if the peer closes the connection before sending a
+/// request. The body is read according to `Content-Length`.
+pub async fn read_request<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    limits: &RequestLimits,
+) -> Result<Option<Request>> {
+    let Some(mut request) = read_request_head(reader, limits).await? else {
+        return Ok(None);
+    };
+    read_request_body(reader, &mut request, limits).await?;
+    Ok(Some(request))
+}
+
+/// Read a request line and headers, leaving the body unread.
+///
+/// Returns `Ok(None)` if the stream ends before a request starts,
+/// [`Error::UriTooLong`] as soon as the request line exceeds its limit, and
+/// [`Error::HeaderTooLarge`] as soon as a header name or value exceeds its,
+/// or the headers exceed their count or total size.
+/// A malformed `Content-Length` fails here too, as does one over
+/// `max_body_bytes` for a buffered body, before any of the body is read.
+pub async fn read_request_head<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    limits: &RequestLimits,
+) -> Result<Option<Request>> {
+    let mut head = String::new();
+    let mut header_count = 0;
+    let mut header_bytes = 0;
+
+    loop {
+        let mut line = Vec::new();
+        let read = if head.is_empty() {
+            // Stop reading just past the limit instead of buffering the whole line
+            let max = limits.max_request_line_bytes;
+            let read = (&mut *reader).take(max as u64 + 2).read_until(b'\n', &mut line).await?;
+            let content = line.strip_suffix(b"\n").unwrap_or(&line);
+            let content = content.strip_suffix(b"\r").unwrap_or(content);
+            if content.len() > max {
+                return Err(Error::UriTooLong(format!("Request line exceeds {} bytes", max)));
+            }
+            read
+        } else {
+            // Bound the line by the name and value limits plus the separator and CRLF
+            let max = limits.max_header_name_bytes + limits.max_header_value_bytes + HEADER_LINE_OVERHEAD;
+            let read = (&mut *reader).take(max as u64).read_until(b'\n', &mut line).await?;
+            if read == max && !line.ends_with(b"\n") {
+                return Err(Error::HeaderTooLarge(format!("Header line exceeds {} bytes", max)));
+            }
+            check_header_field(&line, limits)?;
+            header_bytes += read;
+            if header_bytes > limits.max_header_bytes {
+                return Err(Error::HeaderTooLarge(format!("Headers exceed {} bytes", limits.max_header_bytes)));
+            }
+            read
+        };
+
+        if read == 0 {
+            if head.is_empty() {
+                return Ok(None);
+            }
+            return Err(Error::BadRequest("Connection closed mid-request".to_string()));
+        }
+
+        let line = String::from_utf8_lossy(&line);
+        if line.trim().is_empty() {
+            // Tolerate stray blank lines between pipelined requests
+            if head.is_empty() {
+                continue;
+            }
+            break;
+        }
+        if !head.is_empty() {
+            header_count += 1;
+            if header_count > limits.max_header_count {
+                return Err(Error::HeaderTooLarge(format!("More than {} headers", limits.max_header_count)));
+            }
+        }
+        head.push_str(&line);
+    }
+
+    let request = Request::parse(&head)?;
+    request.check_framing()?;
+    let max_body_bytes = if request.streams_body() { usize::MAX } else { limits.max_body_bytes };
+    request.checked_content_length(max_body_bytes)?;
+    Ok(Some(request))
+}
+
+/// Room in a header line for the colon, optional whitespace and CRLF.
+const HEADER_LINE_OVERHEAD: usize = 16;
+
+/// Check a raw header line's name and value against their limits.
+fn check_header_field(line: &[u8], limits: &RequestLimits) -> Result<()> {
+    let Some(colon) = line.iter().position(|&b| b == b':') else {
+        // Not a header field; parsing reports it
+        return Ok(());
+    };
+
+    if colon > limits.max_header_name_bytes {
+        return Err(Error::HeaderTooLarge(format!(
+            "Header name exceeds {} bytes",
+            limits.max_header_name_bytes
+        )));
+    }
+    let value = &line[colon + 1..];
+    let start = value.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(value.len());
+    let end = value.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(start, |last| last + 1);
+    if end - start > limits.max_header_value_bytes {
+        return Err(Error::HeaderTooLarge(format!(
+            "Header value exceeds {} bytes",
+            limits.max_header_value_bytes
+        )));
+    }
+    Ok(())
+}
+
+/// Read a request's whole body, chunked or sized by `Content-Length`.
+///
+/// Fails with [`Error::PayloadTooLarge`] once the body would exceed
+/// `limits.max_body_bytes`.
+pub async fn read_request_body<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    request: &mut Request,
+    limits: &RequestLimits,
+) -> Result<()> {
+    let mut body_reader = BodyReader::for_request(request, limits.max_body_bytes)?;
+    let mut body = Vec::with_capacity(body_reader.size_hint().min(limits.max_body_bytes));
+    while let Some(chunk) = body_reader.next_chunk(reader).await? {
+        body.extend_from_slice(&chunk);
+    }
+    request.body = body;
+
+    Ok(())
+}
+
+/// Reads a request body piece by piece as it arrives.
+#[derive(Debug)]
+pub struct BodyReader {
+    state: BodyState,
+    /// Bytes the rest of the body may take up
+    allowance: usize,
+    max_bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyState {
+    /// This many `Content-Length` bytes are left
+    Fixed(usize),
+    /// Expecting a chunk-size line
+    ChunkStart,
+    /// This many bytes are left in the current chunk
+    Chunk(usize),
+    Done,
+}
+
+impl BodyReader {
+    /// Prepare to read the body announced by a request's headers.
+    ///
+    /// Bodies longer than `max_bytes` fail with [`Error::PayloadTooLarge`]:
+    /// up front when `Content-Length` is too big, or as soon as a chunk
+    /// would go over it.
+    pub fn for_request(request: &Request, max_bytes: usize) -> Result<Self> {
+        let state = match request.is_chunked() {
+            true => BodyState::ChunkStart,
+            false => BodyState::Fixed(request.checked_content_length(max_bytes)?.unwrap_or(0)),
+        };
+        let allowance = match state {
+            BodyState::Fixed(length) => max_bytes - length,
+            _ => max_bytes,
+        };
+        Ok(Self { state, allowance, max_bytes })
+    }
+
+    /// Count `bytes` against the body's limit.
+    fn take_allowance(&mut self, bytes: usize) -> Result<()> {
+        self.allowance = self.allowance.checked_sub(bytes).ok_or_else(|| {
+            Error::PayloadTooLarge(format!("Request body exceeds {} bytes", self.max_bytes))
+        })?;
+        Ok(())
+    }
+
+    /// Read the next piece of the body, or `None` once it is complete.
+    ///
+    /// Pieces are whatever has arrived, up to the end of the current chunk,
+    /// so a slow client's body is passed on as it trickles in.
+    pub async fn next_chunk<R: AsyncBufRead + Unpin>(&mut self, reader: &mut R) -> Result<Option<Vec<u8>>> {
+        loop {
+            match self.state {
+                BodyState::Done | BodyState::Fixed(0) => {
+                    self.state = BodyState::Done;
+                    return Ok(None);
+                }
+                BodyState::Fixed(left) => {
+                    let piece = read_available(reader, left).await?;
+                    self.state = BodyState::Fixed(left - piece.len());
+                    return Ok(Some(piece));
+                }
+                BodyState::ChunkStart => {
+                    let line = read_chunk_line(reader).await?;
+                    let size = line.split(';').next().unwrap_or_default().trim();
+                    let size = usize::from_str_radix(size, 16)
+                        .map_err(|_| Error::BadRequest(format!("Invalid chunk size: {:?}", size)))?;
+                    if size > 0 {
+                        self.take_allowance(size)?;
+                        self.state = BodyState::Chunk(size);
+                        continue;
+                    }
+                    // Skip any trailers up to the blank line ending the body
+                    while !read_chunk_line(reader).await?.is_empty() {}
+                    self.state = BodyState::Done;
+                }
+                BodyState::Chunk(left) => {
+                    let piece = read_available(reader, left).await?;
+                    let left = left - piece.len();
+                    self.state = if left > 0 { BodyState::Chunk(left) } else { BodyState::ChunkStart };
+                    if left == 0 && !read_chunk_line(reader).await?.is_empty() {
+                        return Err(Error::BadRequest("Chunk is longer than its size".to_string()));
+                    }
+                    return Ok(Some(piece));
+                }
+            }
+        }
+    }
+
+    /// Bytes known to be left, for sizing a buffer.
+    fn size_hint(&self) -> usize {
+        match self.state {
+            BodyState::Fixed(left) => left,
+            _ => 0,
+        }
+    }
+}
+
+/// Take up to `max` bytes of whatever the reader has buffered, waiting for some if it is empty.
+async fn read_available<R: AsyncBufRead + Unpin>(reader: &mut R, max: usize) -> Result<Vec<u8>> {
+    let available = reader.fill_buf().await?;
+    if available.is_empty() {
+        return Err(Error::BadRequest("Connection closed mid-body".to_string()));
+    }
+    let piece = available[..available.len().min(max)].to_vec();
+    reader.consume(piece.len());
+    Ok(piece)
+}
+
+/// Read a line of a chunked body without its CRLF.
+async fn read_chunk_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<String> {
+    let mut line = Vec::new();
+    (&mut *reader).take(MAX_CHUNK_LINE_BYTES as u64).read_until(b'\n', &mut line).await?;
+    let Some(line) = line.strip_suffix(b"\n") else {
+        return Err(Error::BadRequest("Malformed chunked body".to_string()));
+    };
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    Ok(String::from_utf8_lossy(line).into_owned())
+}
+
+/// Get the canonical reason phrase for a status code.
+pub fn status_text(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        304 => "Not Modified",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        416 => "Range Not Satisfiable",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Unknown",
+    }
+}
+
+/// Parse the method and path from the request line.
+pub fn parse_request_line(request: &str) -> Result<(String, String)> {
+    let first_line = request.lines().next()
+        .ok_or_else(|| Error::BadRequest("Empty request".to_string()))?;
+
+    let parts: Vec<&str> = first_line.split_whitespace().collect();
+    if parts.len() < 2 {
+        return Err(Error::BadRequest("Invalid request line".to_string()));
+    }
+
+    Ok((normalize_method(parts[0])?, parts[1].to_string()))
+}
+
+/// Methods the server understands.
+pub const KNOWN_METHODS: [&str; 9] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+/// Uppercase a request method, rejecting ones the server doesn't know.
+pub fn normalize_method(method: &str) -> Result<String> {
+    let method = method.to_ascii_uppercase();
+    if KNOWN_METHODS.contains(&method.as_str()) {
+        Ok(method)
+    } else {
+        Err(Error::NotImplemented(format!("Unsupported method {}", method)))
+    }
+}
+
+/// Parse `Name: value` header lines, skipping malformed ones.
+pub fn parse_headers<'a>(lines: impl Iterator<Item = &'a str>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            headers.append(name.trim(), value.trim());
+        }
+    }
+
+    headers
+}
+
+/// Extract the body from a raw request.
+pub fn extract_body(request: &str) -> String {
+    // Find the empty line that separates headers from body
+    if let Some(body_start) = request.find("\r\n\r\n") {
+        request[body_start + 4..].to_string()
+    } else if let Some(body_start) = request.find("\n\n") {
+        request[body_start + 2..].to_string()
+    } else {
+        String::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request_line() {
+        let request = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let (method, path) = parse_request_line(request).unwrap();
+        assert_eq!(method, "GET");
+        assert_eq!(path, "/");
+    }
+
+    #[test]
+    fn test_parse_request_line_normalizes_method() {
+        let (method, _) = parse_request_line("get /health HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(method, "GET");
+        assert_eq!(normalize_method("Patch").unwrap(), "PATCH");
+
+        let error = parse_request_line("BREW /pot HTTP/1.1\r\n\r\n").unwrap_err();
+        assert!(matches!(error, Error::NotImplemented(_)));
+    }
+
+    #[test]
+    fn test_extract_body() {
+        let request = "POST /process HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        let body = extract_body(request);
+        assert_eq!(body, "hello");
+    }
+
+    #[test]
+    fn test_parse_request() -> Result<()> {
+        let request = Request::parse("POST /process HTTP/1.1\r\nHost: localhost\r\nX-Test: a\r\n\r\nhello")?;
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/process");
+        assert_eq!(request.version, "HTTP/1.1");
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert_eq!(request.header("x-test"), Some("a"));
+        assert_eq!(request.body, b"hello");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_request() -> Result<()> {
+        let raw = b"POST /process HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n\r\n";
+        let mut reader = tokio::io::BufReader::new(&raw[..]);
+
+        let limits = RequestLimits::default();
+
+        let first = read_request(&mut reader, &limits).await?.unwrap();
+        assert_eq!(first.method, "POST");
+        assert_eq!(first.body, b"hello");
+
+        let second = read_request(&mut reader, &limits).await?.unwrap();
+        assert_eq!(second.method, "GET");
+        assert_eq!(second.body, b"");
+
+        assert!(read_request(&mut reader, &limits).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_binary_body() -> Result<()> {
+        let mut raw = b"POST /upload HTTP/1.1\r\nContent-Length: 4\r\n\r\n".to_vec();
+        raw.extend_from_slice(&[0x89, 0x00, 0xff, 0x50]);
+        let mut reader = tokio::io::BufReader::new(&raw[..]);
+
+        let request = read_request(&mut reader, &RequestLimits::default()).await?.unwrap();
+        assert_eq!(request.body_bytes(), &[0x89, 0x00, 0xff, 0x50]);
+        assert!(matches!(request.body_str(), Err(Error::BadRequest(_))));
+
+        let mut request = Request::new("POST", "/");
+        request.body = "héllo".into();
+        assert_eq!(request.body_str()?, "héllo");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_request_head_leaves_body() -> Result<()> {
+        let raw = b"POST /process HTTP/1.1\r\nExpect: 100-Continue\r\nContent-Length: 5\r\n\r\nhello";
+        let mut reader = tokio::io::BufReader::new(&raw[..]);
+
+        let mut request = read_request_head(&mut reader, &RequestLimits::default()).await?.unwrap();
+        assert!(request.expects_continue());
+        assert_eq!(request.body, b"");
+
+        read_request_body(&mut reader, &mut request, &RequestLimits::default()).await?;
+        assert_eq!(request.body, b"hello");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_chunked_body() -> Result<()> {
+        let raw = b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\nX-Trailer: yes\r\n\r\n\
+                    GET / HTTP/1.1\r\n\r\n";
+        let mut reader = tokio::io::BufReader::new(&raw[..]);
+        let limits = RequestLimits::default();
+
+        let request = read_request(&mut reader, &limits).await?.unwrap();
+        assert!(request.is_chunked());
+        assert_eq!(request.body, b"hello, world");
+        assert_eq!(read_request(&mut reader, &limits).await?.unwrap().method, "GET");
+
+        for raw in [
+            &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\nhello\r\n0\r\n\r\n"[..],
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nhello\r\n0\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel",
+        ] {
+            let mut reader = tokio::io::BufReader::new(raw);
+            assert!(matches!(read_request(&mut reader, &limits).await, Err(Error::BadRequest(_))));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_transfer_encoding_framing() -> Result<()> {
+        let limits = RequestLimits::default();
+        let read = |raw: &'static [u8]| async move { read_request(&mut tokio::io::BufReader::new(raw), &limits).await };
+
+        // Every Transfer-Encoding header counts, not just the first
+        let request = read(b"POST / HTTP/1.1\r\nTransfer-Encoding: \r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n0\r\n\r\n");
+        assert_eq!(request.await?.unwrap().body, b"hi");
+
+        for raw in [
+            // Both framings set
+            &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\nhello"[..],
+            b"POST / HTTP/1.1\r\nContent-Length: 0\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+            // Last coding isn't chunked, so the body has no end
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\nhello",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: identity\r\n\r\n",
+            // Chunked twice
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n\r\n",
+        ] {
+            let result = read(raw).await;
+            assert!(matches!(result, Err(Error::BadRequest(_))), "{:?}: {:?}", String::from_utf8_lossy(raw), result);
+        }
+
+        // Codings that would have to be decoded
+        for raw in [
+            &b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n0\r\n\r\n"[..],
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: deflate\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+        ] {
+            let result = read(raw).await;
+            assert!(matches!(result, Err(Error::NotImplemented(_))), "{:?}", result);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_length() {
+        let mut request = Request::new("POST", "/");
+        assert_eq!(request.content_length().unwrap(), None);
+        request.headers.insert("Content-Length", "0");
+        assert_eq!(request.content_length().unwrap(), Some(0));
+        request.headers.append("Content-Length", " 0 ");
+        assert_eq!(request.content_length().unwrap(), Some(0));
+        assert_eq!(request.checked_content_length(0).unwrap(), Some(0));
+        request.headers.insert("Content-Length", "11");
+        assert_eq!(request.checked_content_length(11).unwrap(), Some(11));
+        assert!(matches!(request.checked_content_length(10), Err(Error::PayloadTooLarge(_))));
+
+        for values in [
+            &["-5"][..],
+            &["+5"],
+            &["five"],
+            &[""],
+            &["5 5"],
+            &["99999999999999999999999"],
+            &["5", "6"],
+            &["5, 6"],
+            &["5", "5, x"],
+        ] {
+            let mut request = Request::new("POST", "/");
+            for value in values {
+                request.headers.append("Content-Length", *value);
+            }
+            assert!(matches!(request.content_length(), Err(Error::BadRequest(_))), "{:?}", values);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_header_count_and_size_limits() -> Result<()> {
+        let limits = RequestLimits { max_header_count: 3, max_header_bytes: 64, ..RequestLimits::default() };
+        let raw = b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+        assert!(read_request_head(&mut tokio::io::BufReader::new(&raw[..]), &limits).await?.is_some());
+
+        // Each header passes the per-field limits; together they don't
+        let raw = b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\n\r\n";
+        let result = read_request_head(&mut tokio::io::BufReader::new(&raw[..]), &limits).await;
+        assert!(matches!(result, Err(Error::HeaderTooLarge(_))), "{:?}", result);
+        let raw = format!("GET / HTTP/1.1\r\nA: {}\r\nB: {}\r\n\r\n", "x".repeat(30), "y".repeat(30));
+        let result = read_request_head(&mut tokio::io::BufReader::new(raw.as_bytes()), &limits).await;
+        assert!(matches!(result, Err(Error::HeaderTooLarge(_))), "{:?}", result);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_body_size_limit() -> Result<()> {
+        let limits = RequestLimits { max_body_bytes: 8, ..RequestLimits::default() };
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 8\r\n\r\n12345678";
+        let request = read_request(&mut tokio::io::BufReader::new(&raw[..]), &limits).await?.unwrap();
+        assert_eq!(request.body, b"12345678");
+
+        for raw in [
+            &b"POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\n123456789"[..],
+            // Rejected from the header alone, without allocating for it
+            b"POST / HTTP/1.1\r\nContent-Length: 1000000000000\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n4\r\nworl\r\n0\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\n",
+        ] {
+            let result = read_request(&mut tokio::io::BufReader::new(raw), &limits).await;
+            assert!(matches!(result, Err(Error::PayloadTooLarge(_))), "{:?}", result);
+        }
+
+        // The head alone is enough to reject a buffered body, but not a streamed one
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\n";
+        let result = read_request_head(&mut tokio::io::BufReader::new(&raw[..]), &limits).await;
+        assert!(matches!(result, Err(Error::PayloadTooLarge(_))), "{:?}", result);
+        let raw = b"POST / HTTP/1.1\r\nContent-Type: application/x-ndjson\r\nContent-Length: 9\r\n\r\n";
+        assert!(read_request_head(&mut tokio::io::BufReader::new(&raw[..]), &limits).await?.is_some());
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: -9\r\n\r\n";
+        let result = read_request_head(&mut tokio::io::BufReader::new(&raw[..]), &limits).await;
+        assert!(matches!(result, Err(Error::BadRequest(_))), "{:?}", result);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_body_reader_yields_pieces() -> Result<()> {
+        let (client, server) = tokio::io::duplex(64);
+        let mut reader = tokio::io::BufReader::new(server);
+        let mut request = Request::new("POST", "/");
+        request.headers.insert("Content-Length", "10");
+        let mut body_reader = BodyReader::for_request(&request, 10)?;
+
+        // What has arrived so far is returned without waiting for the rest
+        let mut client = client;
+        tokio::io::AsyncWriteExt::write_all(&mut client, b"hello").await?;
+        assert_eq!(body_reader.next_chunk(&mut reader).await?.unwrap(), b"hello");
+        tokio::io::AsyncWriteExt::write_all(&mut client, b"worldGET").await?;
+        assert_eq!(body_reader.next_chunk(&mut reader).await?.unwrap(), b"world");
+        assert!(body_reader.next_chunk(&mut reader).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_take_body_stream() {
+        let mut request = Request::new("POST", "/");
+        request.body = b"buffered".to_vec();
+        let mut stream = request.take_body_stream();
+        assert_eq!(stream.next_chunk().await.unwrap().unwrap(), b"buffered");
+        assert!(stream.next_chunk().await.is_none());
+
+        request.headers.insert("Content-Type", "application/x-ndjson; charset=utf-8");
+        assert!(!request.streams_body());
+        request.headers.insert("Transfer-Encoding", "chunked");
+        assert!(request.streams_body());
+    }
+
+    #[tokio::test]
+    async fn test_read_request_line_limit() -> Result<()> {
+        let limits = RequestLimits { max_request_line_bytes: 21, ..RequestLimits::default() };
+
+        // Exactly at the limit
+        let raw = b"GET /0123456 HTTP/1.1\r\n\r\n";
+        let mut reader = tokio::io::BufReader::new(&raw[..]);
+        assert!(read_request(&mut reader, &limits).await?.is_some());
+
+        let raw = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(1024));
+        let mut reader = tokio::io::BufReader::new(raw.as_bytes());
+        let result = read_request(&mut reader, &limits).await;
+        assert!(matches!(result, Err(Error::UriTooLong(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_header_field_limits() -> Result<()> {
+        let limits = RequestLimits { max_header_name_bytes: 8, max_header_value_bytes: 16, ..RequestLimits::default() };
+
+        // Exactly at both limits, with padding around the value
+        let raw = format!("GET / HTTP/1.1\r\nX-Header:  {}  \r\n\r\n", "v".repeat(16));
+        let mut reader = tokio::io::BufReader::new(raw.as_bytes());
+        assert!(read_request(&mut reader, &limits).await?.is_some());
+
+        for raw in [
+            format!("GET / HTTP/1.1\r\nX-Header: {}\r\n\r\n", "v".repeat(17)),
+            "GET / HTTP/1.1\r\nX-Too-Long: v\r\n\r\n".to_string(),
+            // Far past the line bound, without ever reaching a newline
+            format!("GET / HTTP/1.1\r\nX-Header: {}", "v".repeat(1 << 20)),
+        ] {
+            let mut reader = tokio::io::BufReader::new(raw.as_bytes());
+            let result = read_request(&mut reader, &limits).await;
+            assert!(matches!(result, Err(Error::HeaderTooLarge(_))), "{:?}", result);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_trace_context_parse() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let context = TraceContext::parse(header).unwrap();
+        assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.span_id, "00f067aa0ba902b7");
+        assert_eq!(context.flags, 1);
+        assert_eq!(context.to_header(), header);
+
+        assert!(TraceContext::parse("garbage").is_none());
+        assert!(TraceContext::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none());
+        assert!(TraceContext::parse("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01").is_none());
+        assert!(TraceContext::parse("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").is_none());
+    }
+
+    #[test]
+    fn test_trace_context_from_request() {
+        let mut request = Request::new("GET", "/");
+        request.headers.insert("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+
+        let adopted = TraceContext::from_request(&request);
+        assert_eq!(adopted.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_ne!(adopted.span_id, "00f067aa0ba902b7");
+
+        request.headers.insert("traceparent", "00-not-a-trace-01");
+        let fresh = TraceContext::from_request(&request);
+        assert!(TraceContext::parse(&fresh.to_header()).is_some());
+    }
+
+    #[test]
+    fn test_create_response() {
+        let response = Response::new(200, "text/plain", "Hello").to_http_string();
+        assert!(response.contains("HTTP/1.1 200 OK"));
+        assert!(response.contains("Content-Type: text/plain"));
+        assert!(response.contains("Content-Length: 5"));
+        assert!(response.contains("Hello"));
+    }
+
+    #[test]
+    fn test_prefers_json() {
+        let request = |accept: &str| {
+            let mut request = Request::new("GET", "/");
+            request.headers.insert("Accept", accept);
+            request
+        };
+
+        assert!(request("application/json").prefers_json());
+        assert!(request("text/html;q=0.5, application/json").prefers_json());
+        assert!(!request("text/html,application/xhtml+xml,application/json;q=0.9").prefers_json());
+        assert!(!request("*/*").prefers_json());
+        assert!(!Request::new("GET", "/").prefers_json());
+    }
+
+    #[test]
+    fn test_request_json_body() {
+        #[derive(serde::Deserialize)]
+        struct Order {
+            item: String,
+            quantity: u32,
+        }
+
+        let mut request = Request::new("POST", "/orders");
+        request.headers.insert("Content-Type", "application/json; charset=utf-8");
+        request.body = r#"{"item":"widget","quantity":3}"#.into();
+
+        let order: Order = request.json().unwrap();
+        assert_eq!(order.item, "widget");
+        assert_eq!(order.quantity, 3);
+
+        request.body = r#"{"item":"widget"}"#.into();
+        assert!(matches!(request.json::<Order>(), Err(Error::BadRequest(_))));
+
+        request.body = r#"{"item":"widget","#.into();
+        let error = request.json::<serde_json::Value>().unwrap_err();
+        assert!(matches!(error, Error::BadRequest(_)));
+        assert_eq!(error.status_code(), 400);
+        assert_eq!(error.code(), "BAD_REQUEST");
+
+        request.headers.insert("Content-Type", "text/plain");
+        assert!(matches!(request.json::<serde_json::Value>(), Err(Error::BadRequest(_))));
+    }
+
+    #[test]
+    fn test_request_form_body() {
+        let mut request = Request::new("POST", "/login");
+        request.headers.insert("Content-Type", "application/x-www-form-urlencoded");
+        request.body = "user=jane+doe&note=50%25%20off&empty=&flag".into();
+
+        assert_eq!(
+            request.form().unwrap(),
+            vec![
+                ("user".to_string(), "jane doe".to_string()),
+                ("note".to_string(), "50% off".to_string()),
+                ("empty".to_string(), String::new()),
+                ("flag".to_string(), String::new()),
+            ]
+        );
+
+        request.body = "bad=%zz".into();
+        assert!(matches!(request.form(), Err(Error::BadRequest(_))));
+
+        request.headers.insert("Content-Type", "application/json");
+        assert!(matches!(request.form(), Err(Error::BadRequest(_))));
+    }
+
+    #[test]
+    fn test_default_charset() {
+        let response = Response::json(200, "{}").with_default_charset();
+        assert_eq!(response.headers.get("Content-Type"), Some("application/json; charset=utf-8"));
+
+        let response = Response::new(200, "application/octet-stream", "").with_default_charset();
+        assert_eq!(response.headers.get("Content-Type"), Some("application/octet-stream"));
+
+        let response = Response::new(200, "text/plain; charset=ISO-8859-1", "").with_default_charset();
+        assert_eq!(response.headers.get("Content-Type"), Some("text/plain; charset=ISO-8859-1"));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_signal() {
+        let (sender, receiver) = watch::channel(false);
+        let signal = ShutdownSignal::new(receiver);
+        assert!(!signal.is_shutting_down());
+
+        let waiting = tokio::spawn({
+            let signal = signal.clone();
+            async move { signal.recv().await }
+        });
+        sender.send(true).unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(1), waiting).await.unwrap().unwrap();
+        assert!(signal.is_shutting_down());
+
+        // Requests built outside the server never see shutdown
+        let request = Request::new("GET", "/");
+        let recv = tokio::time::timeout(std::time::Duration::from_millis(20), request.shutdown.recv());
+        assert!(recv.await.is_err());
+    }
+
+    #[test]
+    fn test_wants_keep_alive() -> Result<()> {
+        let wants_keep_alive = |raw: &str| Request::parse(raw).map(|request| request.wants_keep_alive());
+        assert!(wants_keep_alive("GET / HTTP/1.1\r\n\r\n")?);
+        assert!(!wants_keep_alive("GET / HTTP/1.1\r\nConnection: close\r\n\r\n")?);
+        assert!(!wants_keep_alive("GET / HTTP/1.0\r\n\r\n")?);
+        assert!(wants_keep_alive("GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")?);
+        assert!(!wants_keep_alive("GET / HTTP/1.0\r\nConnection: keep-alive, close\r\n\r\n")?);
+
+        let request = Request::parse("POST / HTTP/1.0\r\nExpect: 100-continue\r\n\r\n")?;
+        assert!(request.is_http10());
+        assert!(!request.expects_continue());
+
+        let response = Response::json(200, "{}").with_version(&request.version);
+        assert!(response.to_http_string().starts_with("HTTP/1.0 200 OK\r\n"));
+        let response = Response::json(200, "{}").with_version("HTTP/2");
+        assert!(response.to_http_string().starts_with("HTTP/1.1 200 OK\r\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_byte_range() -> Result<()> {
+        let request = Request::parse("GET /video.mp4 HTTP/1.1\r\nRange: bytes=0-99\r\n\r\n")?;
+        let RangeMatch::Partial(range) = request.byte_range(1000) else {
+            panic!("expected a partial range");
+        };
+        assert_eq!(range, ByteRange { start: 0, end: 99 });
+        assert_eq!(range.len(), 100);
+        assert_eq!(range.content_range(1000), "bytes 0-99/1000");
+
+        // Open-ended and suffix ranges run to the end; ends are clamped
+        let partial = |start, end| RangeMatch::Partial(ByteRange { start, end });
+        assert_eq!(RangeMatch::parse(Some("bytes=900-"), 1000), partial(900, 999));
+        assert_eq!(RangeMatch::parse(Some("bytes=-100"), 1000), partial(900, 999));
+        assert_eq!(RangeMatch::parse(Some("bytes=-5000"), 1000), partial(0, 999));
+        assert_eq!(RangeMatch::parse(Some("bytes=990-2000"), 1000), partial(990, 999));
+
+        for unsatisfiable in ["bytes=1000-", "bytes=5000-6000", "bytes=-0"] {
+            assert_eq!(RangeMatch::parse(Some(unsatisfiable), 1000), RangeMatch::Unsatisfiable);
+        }
+        let response = Response::range_not_satisfiable(1000);
+        assert_eq!(response.status, 416);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes */1000"));
+        assert!(response.to_http_string().starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+
+        for ignored in [None, Some("items=0-9"), Some("bytes=0-1,5-6"), Some("bytes=9-3"), Some("bytes=x-")] {
+            assert_eq!(RangeMatch::parse(ignored, 1000), RangeMatch::Full);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_content_response() -> Result<()> {
+        let body: Vec<u8> = (0..=255u8).collect();
+
+        // Closed range
+        let request = Request::parse("GET /file.bin HTTP/1.1\r\nRange: bytes=10-19\r\n\r\n")?;
+        let response = Response::ranged("application/octet-stream", &body, request.byte_range(body.len() as u64));
+        assert_eq!(response.status, 206);
+        assert_eq!(response.body, &body[10..20]);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 10-19/256"));
+        let http = response.to_http_bytes();
+        let head_end = http.windows(4).position(|w| w == b"\r\n\r\n").expect("head") + 4;
+        let head = std::str::from_utf8(&http[..head_end]).expect("text head");
+        assert!(head.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(head.contains("Content-Length: 10\r\n"));
+        assert!(head.contains("Accept-Ranges: bytes\r\n"));
+        assert_eq!(&http[head_end..], &body[10..20]);
+
+        // Open-ended range runs to the end of the body
+        let request = Request::parse("GET /file.bin HTTP/1.1\r\nRange: bytes=250-\r\n\r\n")?;
+        let response = Response::ranged("application/octet-stream", &body, request.byte_range(body.len() as u64));
+        assert_eq!(response.status, 206);
+        assert_eq!(response.body, &body[250..]);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 250-255/256"));
+        assert!(response.to_http_string().contains("Content-Length: 6\r\n"));
+
+        // Out of bounds
+        let request = Request::parse("GET /file.bin HTTP/1.1\r\nRange: bytes=300-400\r\n\r\n")?;
+        let response = Response::ranged("application/octet-stream", &body, request.byte_range(body.len() as u64));
+        assert_eq!(response.status, 416);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes */256"));
+        assert!(response.body.is_empty());
+        let response = Response::partial_content("application/octet-stream", &body, ByteRange { start: 200, end: 300 });
+        assert_eq!(response.status, 416);
+
+        // No range sends the whole body
+        let response = Response::ranged("application/octet-stream", &body, RangeMatch::Full);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, body);
+        Ok(())
+    }
+
+    #[test]
+    fn test_response_multi_value_headers() {
+        let response = Response::json(200, "{}")
+            .append_header("Set-Cookie", "session=abc; HttpOnly")
+            .append_header("Set-Cookie", "theme=dark")
+            .with_header("Cache-Control", "no-store");
+        let values: Vec<_> = response.headers.get_all("set-cookie").collect();
+        assert_eq!(values, ["session=abc; HttpOnly", "theme=dark"]);
+
+        let http = response.to_http_string();
+        assert!(http.contains("Set-Cookie: session=abc; HttpOnly\r\nSet-Cookie: theme=dark\r\n"), "{}", http);
+        assert_eq!(http.matches("Set-Cookie:").count(), 2);
+
+        // with_header still replaces every earlier value
+        let http = response.with_header("Set-Cookie", "cleared=1").to_http_string();
+        assert_eq!(http.matches("Set-Cookie:").count(), 1);
+        assert!(http.contains("Set-Cookie: cleared=1\r\n"));
+    }
+}
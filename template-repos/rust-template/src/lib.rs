@@ -2,20 +2,121 @@
 //! 
 //! A minimal Rust project template with modern tooling.
 
+pub mod auth;
 pub mod config;
 pub mod error;
+pub mod health;
+pub mod http;
+pub mod logging;
+pub mod metrics;
+pub mod middleware;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod router;
+pub mod server;
+pub mod tls;
 pub mod utils;
 
+#[cfg(test)]
+mod testing;
+
 pub use config::Config;
 pub use error::{Error, Result};
 
+use std::borrow::Cow;
+use std::time::Instant;
+
+use serde::Serialize;
+use unicode_normalization::UnicodeNormalization;
+
+/// What [`process_data_with`] does with empty input.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum OnEmpty {
+    /// Fail with [`Error::InvalidInput`]
+    #[default]
+    Error,
+    /// Return an empty string
+    Empty,
+    /// Return this value unprocessed
+    Default(String),
+}
+
+/// Unicode normalization form applied to input before processing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeForm {
+    /// Canonical composition, e.g. `e` + combining acute becomes `é`
+    Nfc,
+    /// Canonical decomposition, e.g. `é` becomes `e` + combining acute
+    Nfd,
+}
+
+/// Options controlling [`process_data_with`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessOptions {
+    /// Handling of empty input
+    pub on_empty: OnEmpty,
+    /// Normalization applied before case mapping, none by default
+    pub normalize: Option<UnicodeForm>,
+    /// Most characters of input processed; the rest is dropped
+    pub max_len: Option<usize>,
+}
+
+/// Output of [`process_data_detailed`] with details of the run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProcessResult {
+    /// The processed output
+    pub output: String,
+    /// Length of the input in characters, before truncation
+    pub input_len: usize,
+    /// Whether the input was cut to [`ProcessOptions::max_len`]
+    pub truncated: bool,
+    /// Processing time in microseconds
+    pub duration_us: u64,
+}
+
 /// Main library function for demonstration.
 pub fn process_data(input: &str) -> Result<String> {
+    process_data_with(input, &ProcessOptions::default())
+}
+
+/// Process input according to `options`.
+pub fn process_data_with(input: &str, options: &ProcessOptions) -> Result<String> {
+    process(input, options).map(|(output, _)| output)
+}
+
+/// Process input according to `options`, reporting details of the run.
+pub fn process_data_detailed(input: &str, options: &ProcessOptions) -> Result<ProcessResult> {
+    let started = Instant::now();
+    let (output, truncated) = process(input, options)?;
+
+    Ok(ProcessResult {
+        output,
+        input_len: input.chars().count(),
+        truncated,
+        duration_us: u64::try_from(started.elapsed().as_micros()).unwrap_or(u64::MAX),
+    })
+}
+
+/// Process input, also returning whether it was truncated.
+fn process(input: &str, options: &ProcessOptions) -> Result<(String, bool)> {
     if input.is_empty() {
-        return Err(Error::InvalidInput("Input cannot be empty".to_string()));
+        return match &options.on_empty {
+            OnEmpty::Error => Err(Error::InvalidInput("Input cannot be empty".to_string())),
+            OnEmpty::Empty => Ok((String::new(), false)),
+            OnEmpty::Default(value) => Ok((value.clone(), false)),
+        };
     }
     
-    Ok(format!("Processed: {}", input.to_uppercase()))
+    let (input, truncated) = match options.max_len.and_then(|max| input.char_indices().nth(max)) {
+        Some((end, _)) => (&input[..end], true),
+        None => (input, false),
+    };
+    let input: Cow<str> = match options.normalize {
+        Some(UnicodeForm::Nfc) => input.nfc().collect::<String>().into(),
+        Some(UnicodeForm::Nfd) => input.nfd().collect::<String>().into(),
+        None => input.into(),
+    };
+    Ok((format!("Processed: {}", input.to_uppercase()), truncated))
 }
 
 #[cfg(test)]
@@ -33,4 +134,61 @@ mod tests {
         let result = process_data("");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_process_data_on_empty() {
+        let options = ProcessOptions::default();
+        assert!(matches!(process_data_with("", &options), Err(Error::InvalidInput(_))));
+
+        let options = ProcessOptions { on_empty: OnEmpty::Empty, ..ProcessOptions::default() };
+        assert_eq!(process_data_with("", &options).unwrap(), "");
+
+        let options = ProcessOptions { on_empty: OnEmpty::Default("n/a".to_string()), ..ProcessOptions::default() };
+        assert_eq!(process_data_with("", &options).unwrap(), "n/a");
+
+        // Non-empty input is processed as usual whatever the policy
+        assert_eq!(process_data_with("hi", &options).unwrap(), "Processed: HI");
+    }
+
+    #[test]
+    fn test_process_data_normalize() {
+        let composed = "caf\u{e9}";
+        let decomposed = "cafe\u{301}";
+        assert_ne!(process_data(composed).unwrap(), process_data(decomposed).unwrap());
+
+        let nfc = ProcessOptions { normalize: Some(UnicodeForm::Nfc), ..ProcessOptions::default() };
+        assert_eq!(process_data_with(composed, &nfc).unwrap(), "Processed: CAF\u{c9}");
+        assert_eq!(process_data_with(decomposed, &nfc).unwrap(), "Processed: CAF\u{c9}");
+
+        let nfd = ProcessOptions { normalize: Some(UnicodeForm::Nfd), ..ProcessOptions::default() };
+        assert_eq!(process_data_with(composed, &nfd).unwrap(), "Processed: CAFE\u{301}");
+        assert_eq!(process_data_with(decomposed, &nfd).unwrap(), "Processed: CAFE\u{301}");
+    }
+
+    #[test]
+    fn test_process_data_detailed() {
+        let result = process_data_detailed("h\u{e9}llo", &ProcessOptions::default()).unwrap();
+        assert_eq!(result.output, "Processed: H\u{c9}LLO");
+        assert_eq!(result.input_len, 5);
+        assert!(!result.truncated);
+
+        let options = ProcessOptions { max_len: Some(3), ..ProcessOptions::default() };
+        let result = process_data_detailed("h\u{e9}llo", &options).unwrap();
+        assert_eq!(result.output, "Processed: H\u{c9}L");
+        assert_eq!(result.input_len, 5);
+        assert!(result.truncated);
+        assert_eq!(process_data_with("h\u{e9}llo", &options).unwrap(), result.output);
+
+        // Input exactly at the limit isn't truncated
+        let result = process_data_detailed("abc", &options).unwrap();
+        assert!(!result.truncated);
+
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["output"], "Processed: ABC");
+        assert_eq!(json["input_len"], 3);
+        assert_eq!(json["truncated"], false);
+        assert!(json["duration_us"].is_u64());
+
+        assert!(matches!(process_data_detailed("", &options), Err(Error::InvalidInput(_))));
+    }
 }
\ No newline at end of file
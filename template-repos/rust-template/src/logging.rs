@@ -0,0 +1,65 @@
+//! Process-wide tracing subscriber setup.
+
+use tracing_subscriber::EnvFilter;
+
+use crate::config::LoggingConfig;
+use crate::error::{Error, Result};
+
+/// Install the global tracing subscriber described by `config`.
+///
+/// `level` takes a level or `RUST_LOG`-style directives, and `format` selects
+/// `compact` or `pretty` output; anything else uses the default full format.
+/// Logs go to stderr. Returns `Ok(())` without changing anything if a global
+/// subscriber is already installed, so binaries and tests can call it freely.
+pub fn try_init(config: &LoggingConfig) -> Result<()> {
+    if tracing::dispatcher::has_been_set() {
+        return Ok(());
+    }
+
+    let filter = EnvFilter::try_new(&config.level)
+        .map_err(|e| Error::Config(format!("Invalid log level {:?}: {}", config.level, e)))?;
+    let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr);
+    let result = match config.format.as_str() {
+        "compact" => builder.compact().try_init(),
+        "pretty" => builder.pretty().try_init(),
+        _ => builder.try_init(),
+    };
+
+    match result {
+        // Another thread may have installed one since the check above
+        Err(_) if tracing::dispatcher::has_been_set() => Ok(()),
+        Err(e) => Err(Error::Internal(format!("Failed to install log subscriber: {}", e))),
+        Ok(()) => Ok(()),
+    }
+}
+
+/// Install a global subscriber that logs at debug level through the test
+/// harness, which only shows output for failing tests.
+///
+/// Safe to call from every test; only the first call has any effect.
+pub fn init_for_tests() {
+    if tracing::dispatcher::has_been_set() {
+        return;
+    }
+
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new("debug"))
+        .with_test_writer()
+        .try_init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_init_is_idempotent() -> Result<()> {
+        let config = LoggingConfig { level: "debug".to_string(), ..LoggingConfig::default() };
+        try_init(&config)?;
+        try_init(&config)?;
+        init_for_tests();
+        init_for_tests();
+        assert!(tracing::dispatcher::has_been_set());
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+//! Metric snapshots and export formats.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+
+/// Point-in-time copy of every metric, sorted by name.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MetricsSnapshot {
+    /// Counter totals
+    pub counters: BTreeMap<String, u64>,
+
+    /// Latest gauge values
+    pub gauges: BTreeMap<String, f64>,
+
+    /// Histogram observations
+    pub histograms: BTreeMap<String, Histogram>,
+
+    /// When the snapshot was taken (seconds since Unix epoch)
+    pub timestamp: u64,
+}
+
+/// Upper bounds, in seconds, of the buckets used for latency histograms.
+pub const DEFAULT_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Distribution of observed values over fixed buckets.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Histogram {
+    /// Cumulative bucket counts in ascending order of upper bound
+    pub buckets: Vec<Bucket>,
+
+    /// Number of observations
+    pub count: u64,
+
+    /// Sum of all observations
+    pub sum: f64,
+}
+
+/// Observations less than or equal to `le`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Bucket {
+    /// Inclusive upper bound
+    pub le: f64,
+
+    /// Number of observations at or below the bound
+    pub count: u64,
+}
+
+impl Histogram {
+    /// Create an empty histogram with the given ascending bucket bounds.
+    pub fn new(bounds: &[f64]) -> Self {
+        Self {
+            buckets: bounds.iter().map(|&le| Bucket { le, count: 0 }).collect(),
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    /// Record one observation.
+    pub fn observe(&mut self, value: f64) {
+        for bucket in self.buckets.iter_mut().filter(|bucket| value <= bucket.le) {
+            bucket.count += 1;
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new(&DEFAULT_BUCKETS)
+    }
+}
+
+/// Renders a snapshot in a particular wire format.
+pub trait MetricsExporter {
+    /// Render the snapshot.
+    fn export(&self, snapshot: &MetricsSnapshot) -> String;
+}
+
+/// Wire format for exposing metrics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsFormat {
+    /// [`JsonExporter`]
+    #[default]
+    Json,
+    /// [`PrometheusExporter`]
+    Prometheus,
+    /// [`StatsdExporter`]
+    Statsd,
+}
+
+impl MetricsFormat {
+    /// Exporter producing this format.
+    pub fn exporter(&self) -> Box<dyn MetricsExporter + Send + Sync> {
+        match self {
+            MetricsFormat::Json => Box::new(JsonExporter),
+            MetricsFormat::Prometheus => Box::new(PrometheusExporter),
+            MetricsFormat::Statsd => Box::new(StatsdExporter),
+        }
+    }
+
+    /// `Content-Type` to serve this format with.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            MetricsFormat::Json => "application/json",
+            MetricsFormat::Prometheus => "text/plain; version=0.0.4",
+            MetricsFormat::Statsd => "text/plain",
+        }
+    }
+}
+
+/// JSON object with `counters`, `gauges`, `histograms` and `timestamp` keys.
+///
+/// Labeled series are keyed by their full `name{key="value"}` name.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonExporter;
+
+impl MetricsExporter for JsonExporter {
+    fn export(&self, snapshot: &MetricsSnapshot) -> String {
+        // String keys and numeric values always serialize
+        serde_json::to_string(snapshot).unwrap_or_default()
+    }
+}
+
+/// Prometheus text exposition format.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PrometheusExporter;
+
+impl MetricsExporter for PrometheusExporter {
+    fn export(&self, snapshot: &MetricsSnapshot) -> String {
+        let mut output = String::new();
+
+        for (name, series) in group_series(&snapshot.counters) {
+            let _ = writeln!(output, "# TYPE {} counter", name);
+            for (labels, value) in series {
+                let _ = writeln!(output, "{}{} {}", name, labels, value);
+            }
+        }
+        for (name, series) in group_series(&snapshot.gauges) {
+            let _ = writeln!(output, "# TYPE {} gauge", name);
+            for (labels, value) in series {
+                let _ = writeln!(output, "{}{} {}", name, labels, value);
+            }
+        }
+        for (name, histogram) in &snapshot.histograms {
+            let name = prometheus_name(name);
+            let _ = writeln!(output, "# TYPE {} histogram", name);
+            for bucket in &histogram.buckets {
+                let _ = writeln!(output, "{}_bucket{{le=\"{}\"}} {}", name, bucket.le, bucket.count);
+            }
+            let _ = writeln!(output, "{}_bucket{{le=\"+Inf\"}} {}", name, histogram.count);
+            let _ = writeln!(output, "{}_sum {}\n{}_count {}", name, histogram.sum, name, histogram.count);
+        }
+
+        output
+    }
+}
+
+/// StatsD line protocol, one `name:value|type` line per metric.
+///
+/// Counters are written as their running totals; histograms as their
+/// observation count and sum. Labels become DogStatsD tags, as in
+/// `name:value|c|#key:value`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StatsdExporter;
+
+impl MetricsExporter for StatsdExporter {
+    fn export(&self, snapshot: &MetricsSnapshot) -> String {
+        let mut output = String::new();
+
+        for (series, value) in &snapshot.counters {
+            let (name, tags) = statsd_series(series);
+            let _ = writeln!(output, "{}:{}|c{}", name, value, tags);
+        }
+        for (series, value) in &snapshot.gauges {
+            let (name, tags) = statsd_series(series);
+            let _ = writeln!(output, "{}:{}|g{}", name, value, tags);
+        }
+        for (name, histogram) in &snapshot.histograms {
+            let _ = writeln!(output, "{}.count:{}|c\n{}.sum:{}|g", name, histogram.count, name, histogram.sum);
+        }
+
+        output
+    }
+}
+
+/// Group `name{labels}` series under their sanitized metric name.
+fn group_series<T: Copy>(metrics: &BTreeMap<String, T>) -> BTreeMap<String, Vec<(&str, T)>> {
+    let mut grouped: BTreeMap<String, Vec<(&str, T)>> = BTreeMap::new();
+    for (key, &value) in metrics {
+        let (name, labels) = match key.find('{') {
+            Some(index) => key.split_at(index),
+            None => (key.as_str(), ""),
+        };
+        grouped.entry(prometheus_name(name)).or_default().push((labels, value));
+    }
+    grouped
+}
+
+/// Split a `name{labels}` series into its name and a DogStatsD `|#tags` suffix.
+fn statsd_series(series: &str) -> (&str, String) {
+    let Some(index) = series.find('{') else {
+        return (series, String::new());
+    };
+    let tags: Vec<String> = parse_labels(&series[index..])
+        .into_iter()
+        .map(|(key, value)| {
+            // Commas, pipes and newlines would end the tag or the line
+            let value: String = value
+                .chars()
+                .map(|c| if matches!(c, ',' | '|' | '#' | '\n') { '_' } else { c })
+                .collect();
+            format!("{}:{}", key, value)
+        })
+        .collect();
+    (&series[..index], format!("|#{}", tags.join(",")))
+}
+
+/// Parse `{key="value",...}` as written for a labeled series, unescaping values.
+fn parse_labels(labels: &str) -> Vec<(&str, String)> {
+    let mut parsed = Vec::new();
+    let mut rest = labels.strip_prefix('{').unwrap_or(labels);
+    while let Some((key, after)) = rest.split_once("=\"") {
+        let mut value = String::new();
+        let mut chars = after.char_indices();
+        let mut end = after.len();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, escaped)) => value.push(escaped),
+                    None => {}
+                },
+                '"' => {
+                    end = i + 1;
+                    break;
+                }
+                c => value.push(c),
+            }
+        }
+        parsed.push((key, value));
+        rest = after[end..].trim_start_matches(',');
+    }
+    parsed
+}
+
+/// Map a label key onto the characters Prometheus allows, `[a-zA-Z_][a-zA-Z0-9_]*`.
+pub(crate) fn label_name(key: &str) -> String {
+    let mut sanitized: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+
+    if sanitized.is_empty() || sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+/// Map a metric name onto the characters Prometheus allows.
+fn prometheus_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == ':' { c } else { '_' })
+        .collect();
+
+    if sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> MetricsSnapshot {
+        MetricsSnapshot {
+            counters: BTreeMap::from([
+                ("http.requests".to_string(), 42),
+                ("errors".to_string(), 3),
+            ]),
+            gauges: BTreeMap::from([("memory_mb".to_string(), 12.5)]),
+            histograms: BTreeMap::new(),
+            timestamp: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn test_json_exporter() {
+        let output: serde_json::Value = serde_json::from_str(&JsonExporter.export(&snapshot())).unwrap();
+        assert_eq!(output["counters"]["http.requests"], 42);
+        assert_eq!(output["gauges"]["memory_mb"], 12.5);
+        assert_eq!(output["timestamp"], 1_700_000_000);
+    }
+
+    #[test]
+    fn test_prometheus_exporter() {
+        assert_eq!(
+            PrometheusExporter.export(&snapshot()),
+            "# TYPE errors counter\n\
+             errors 3\n\
+             # TYPE http_requests counter\n\
+             http_requests 42\n\
+             # TYPE memory_mb gauge\n\
+             memory_mb 12.5\n"
+        );
+        assert_eq!(prometheus_name("5xx.rate"), "_5xx_rate");
+    }
+
+    #[test]
+    fn test_statsd_exporter() {
+        assert_eq!(
+            StatsdExporter.export(&snapshot()),
+            "errors:3|c\nhttp.requests:42|c\nmemory_mb:12.5|g\n"
+        );
+    }
+
+    #[test]
+    fn test_statsd_labeled_series() {
+        let snapshot = MetricsSnapshot {
+            counters: BTreeMap::from([
+                ("http.requests{method=\"POST\",route=\"/a\"}".to_string(), 3),
+                ("logins{user=\"a\\\"b,c|d\"}".to_string(), 1),
+            ]),
+            gauges: BTreeMap::from([("queue_depth{queue=\"jobs\"}".to_string(), 4.0)]),
+            ..MetricsSnapshot::default()
+        };
+        assert_eq!(
+            StatsdExporter.export(&snapshot),
+            "http.requests:3|c|#method:POST,route:/a\n\
+             logins:1|c|#user:a\"b_c_d\n\
+             queue_depth:4|g|#queue:jobs\n"
+        );
+        assert_eq!(label_name("http.method"), "http_method");
+        assert_eq!(label_name("2xx"), "_2xx");
+        assert_eq!(label_name(""), "_");
+    }
+
+    #[test]
+    fn test_histogram_export() {
+        let mut latency = Histogram::new(&[0.1, 1.0]);
+        latency.observe(0.05);
+        latency.observe(0.5);
+        latency.observe(3.0);
+        assert_eq!(latency.buckets[0].count, 1);
+        assert_eq!(latency.buckets[1].count, 2);
+
+        let snapshot = MetricsSnapshot {
+            histograms: BTreeMap::from([("latency".to_string(), latency)]),
+            ..MetricsSnapshot::default()
+        };
+        assert_eq!(
+            PrometheusExporter.export(&snapshot),
+            "# TYPE latency histogram\n\
+             latency_bucket{le=\"0.1\"} 1\n\
+             latency_bucket{le=\"1\"} 2\n\
+             latency_bucket{le=\"+Inf\"} 3\n\
+             latency_sum 3.55\n\
+             latency_count 3\n"
+        );
+        assert_eq!(StatsdExporter.export(&snapshot), "latency.count:3|c\nlatency.sum:3.55|g\n");
+    }
+
+    #[test]
+    fn test_prometheus_labeled_series() {
+        let snapshot = MetricsSnapshot {
+            counters: BTreeMap::from([
+                ("http.requests".to_string(), 2),
+                ("http.requests{route=\"/a\"}".to_string(), 3),
+                ("http.requests_failed".to_string(), 1),
+            ]),
+            ..MetricsSnapshot::default()
+        };
+        assert_eq!(
+            PrometheusExporter.export(&snapshot),
+            "# TYPE http_requests counter\n\
+             http_requests 2\n\
+             http_requests{route=\"/a\"} 3\n\
+             # TYPE http_requests_failed counter\n\
+             http_requests_failed 1\n"
+        );
+    }
+}
@@ -0,0 +1,591 @@
+//! Middleware run around every request handler.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tracing::{info, Span};
+
+use crate::auth::{Authenticator, Identity};
+use crate::http::{Request, Response};
+use crate::server::Handler;
+use crate::utils::{generate_random_string, RateLimitDecision, RateLimiter};
+
+/// Most clients [`RateLimit`] tracks separately by default.
+const MAX_RATE_LIMIT_KEYS: usize = 10_000;
+
+/// Code run before and after the request handler.
+///
+/// A middleware either calls [`Next::run`] to continue down the chain or
+/// returns a response of its own, skipping everything after it.
+#[async_trait]
+pub trait Middleware: Send + Sync {
+    /// Handle a request, usually by passing it to `next`.
+    async fn handle(&self, request: Request, next: Next) -> Response;
+}
+
+/// The rest of the middleware chain, ending in the request handler.
+pub struct Next {
+    middlewares: Arc<[Arc<dyn Middleware>]>,
+    index: usize,
+    handler: Handler,
+}
+
+impl Next {
+    /// Run the remaining middleware and the handler.
+    pub async fn run(self, request: Request) -> Response {
+        match self.middlewares.get(self.index).cloned() {
+            Some(middleware) => {
+                let next = Next { index: self.index + 1, ..self };
+                middleware.handle(request, next).await
+            }
+            None => (self.handler)(request).await,
+        }
+    }
+}
+
+/// Middleware applied in the order it was added, first outermost.
+#[derive(Default, Clone)]
+pub struct MiddlewareStack {
+    middlewares: Vec<Arc<dyn Middleware>>,
+}
+
+impl MiddlewareStack {
+    /// Create an empty stack.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a middleware inside those already added.
+    pub fn layer(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Add every middleware from `other` inside those already added.
+    pub fn extend(mut self, other: MiddlewareStack) -> Self {
+        self.middlewares.extend(other.middlewares);
+        self
+    }
+
+    /// Number of middlewares in the stack.
+    pub fn len(&self) -> usize {
+        self.middlewares.len()
+    }
+
+    /// Check if the stack is empty.
+    pub fn is_empty(&self) -> bool {
+        self.middlewares.is_empty()
+    }
+
+    /// Wrap a handler so every request passes through the stack first.
+    pub fn wrap(self, handler: Handler) -> Handler {
+        if self.middlewares.is_empty() {
+            return handler;
+        }
+
+        let middlewares: Arc<[Arc<dyn Middleware>]> = self.middlewares.into();
+        Arc::new(move |request| {
+            let next = Next { middlewares: middlewares.clone(), index: 0, handler: handler.clone() };
+            Box::pin(next.run(request))
+        })
+    }
+}
+
+/// Logs each request once it has been answered.
+///
+/// Responses other than 2xx are always logged; successful ones are logged
+/// for a random `sample_rate` fraction of requests.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestLogger {
+    sample_rate: f64,
+}
+
+impl Default for RequestLogger {
+    fn default() -> Self {
+        Self { sample_rate: 1.0 }
+    }
+}
+
+impl RequestLogger {
+    /// Log successful requests with probability `sample_rate`, from 0 to 1.
+    pub fn sampled(sample_rate: f64) -> Self {
+        Self { sample_rate: if sample_rate.is_nan() { 1.0 } else { sample_rate.clamp(0.0, 1.0) } }
+    }
+
+    /// Whether to log a request that succeeded.
+    fn sample(&self) -> bool {
+        self.sample_rate >= 1.0 || (self.sample_rate > 0.0 && rand::random::<f64>() < self.sample_rate)
+    }
+}
+
+#[async_trait]
+impl Middleware for RequestLogger {
+    async fn handle(&self, request: Request, next: Next) -> Response {
+        let (method, path, version) = (request.method.clone(), request.path.clone(), request.version.clone());
+        let started = Instant::now();
+        let response = next.run(request).await;
+
+        if !(200..300).contains(&response.status) || self.sample() {
+            info!(
+                status = response.status,
+                elapsed_ms = started.elapsed().as_millis() as u64,
+                "Request completed: {} {} {} -> {}",
+                method,
+                path,
+                version,
+                response.status
+            );
+        }
+        response
+    }
+}
+
+/// Tags each request with an ID, echoed back in the response.
+///
+/// A well-formed incoming ID is kept so it can be correlated across
+/// services; otherwise a new one is generated. The ID is also recorded on
+/// the current span's `request_id` field.
+pub struct RequestId {
+    header: String,
+}
+
+impl RequestId {
+    /// Read and write the ID in `header`.
+    pub fn new(header: impl Into<String>) -> Self {
+        Self { header: header.into() }
+    }
+}
+
+#[async_trait]
+impl Middleware for RequestId {
+    async fn handle(&self, mut request: Request, next: Next) -> Response {
+        let id = ensure_request_id(&mut request, &self.header);
+        Span::current().record("request_id", id.as_str());
+
+        next.run(request).await.with_header(&self.header, id)
+    }
+}
+
+/// Get the request's ID from `header`, first setting a new one if it is missing or malformed.
+pub(crate) fn ensure_request_id(request: &mut Request, header: &str) -> String {
+    match request.header(header) {
+        Some(id) if is_valid_request_id(id) => id.to_string(),
+        _ => {
+            let id = generate_random_string(16);
+            request.headers.insert(header, id.clone());
+            id
+        }
+    }
+}
+
+/// Accept short IDs of visible ASCII so they are safe to log and echo.
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Rejects unauthenticated requests to the given paths with a 401.
+///
+/// Paths match regardless of query string or trailing slashes, so a
+/// protected route can't be reached around the check by a redirect or merge.
+pub struct RequireAuth {
+    authenticator: Arc<dyn Authenticator>,
+    paths: Vec<String>,
+}
+
+impl RequireAuth {
+    /// Protect `paths` with `authenticator`.
+    pub fn new(authenticator: Arc<dyn Authenticator>, paths: &[&str]) -> Self {
+        Self {
+            authenticator,
+            paths: paths.iter().map(|path| normalize_path(path).to_string()).collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for RequireAuth {
+    async fn handle(&self, request: Request, next: Next) -> Response {
+        if self.paths.iter().any(|path| path == normalize_path(&request.path)) {
+            if let Err(e) = self.authenticator.authenticate(&request.headers).await {
+                return Response::json(401, serde_json::json!({"error": e.to_string(), "status": "error"}).to_string());
+            }
+        }
+        next.run(request).await
+    }
+}
+
+/// What identifies a client for rate limiting.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    /// The connection's peer IP address (the default)
+    #[default]
+    PeerIp,
+    /// The last comma-separated value of a header, such as `X-Forwarded-For`
+    ///
+    /// Only the rightmost hop was added by the proxy in front of the server;
+    /// earlier values come from the client and can be anything.
+    Header(String),
+    /// The caller authenticated by the API key header
+    ApiKey,
+}
+
+impl RateLimitKey {
+    /// The key a request is counted under.
+    ///
+    /// `identity` is the caller authenticated from the request, used by
+    /// [`RateLimitKey::ApiKey`]. A missing or empty header, or a missing
+    /// identity, falls back to the peer IP, so unchecked keys can't mint fresh
+    /// budgets. Keys are prefixed with their source so a header value can't
+    /// pose as another client's IP.
+    pub fn extract(&self, request: &Request, identity: Option<&Identity>) -> String {
+        let key = match self {
+            RateLimitKey::PeerIp => None,
+            RateLimitKey::Header(name) => request
+                .headers
+                .get_all(name)
+                .last()
+                .and_then(|value| value.rsplit(',').next())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(|value| format!("header:{}", value)),
+            RateLimitKey::ApiKey => identity.map(|identity| format!("user:{}", identity.subject)),
+        };
+
+        key.unwrap_or_else(|| match request.peer {
+            Some(peer) => format!("ip:{}", peer.ip()),
+            None => "ip:unknown".to_string(),
+        })
+    }
+}
+
+/// Answers clients over their request budget with a 429.
+///
+/// Each key from [`RateLimitKey`] gets its own sliding window of `limit`
+/// requests. Once `max_keys` clients are tracked, new ones share a single
+/// overflow window until idle clients are swept out, at most once a window.
+pub struct RateLimit {
+    key: RateLimitKey,
+    limit: usize,
+    window: Duration,
+    max_keys: usize,
+    limiters: Mutex<Limiters>,
+    authenticator: Option<Arc<dyn Authenticator>>,
+}
+
+/// Per-client windows and the window shared by clients past the cap.
+struct Limiters {
+    clients: HashMap<String, RateLimiter>,
+    overflow: RateLimiter,
+    last_sweep: Instant,
+}
+
+impl RateLimit {
+    /// Allow each client `limit` requests per `window`.
+    pub fn new(key: RateLimitKey, limit: usize, window: Duration) -> Self {
+        let limiters = Limiters {
+            clients: HashMap::new(),
+            overflow: RateLimiter::new(limit, window),
+            last_sweep: Instant::now(),
+        };
+        Self {
+            key,
+            limit,
+            window,
+            max_keys: MAX_RATE_LIMIT_KEYS,
+            limiters: Mutex::new(limiters),
+            authenticator: None,
+        }
+    }
+
+    /// Track at most `max_keys` clients separately.
+    pub fn with_max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = max_keys;
+        self
+    }
+
+    /// Number of clients currently tracked separately.
+    pub fn tracked_keys(&self) -> usize {
+        self.limiters.lock().unwrap_or_else(PoisonError::into_inner).clients.len()
+    }
+
+    /// Identify callers with `authenticator` for [`RateLimitKey::ApiKey`].
+    ///
+    /// Without one every request is counted under its peer IP.
+    pub fn with_authenticator(mut self, authenticator: Arc<dyn Authenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+}
+
+#[async_trait]
+impl Middleware for RateLimit {
+    async fn handle(&self, request: Request, next: Next) -> Response {
+        let identity = match (&self.key, &self.authenticator) {
+            (RateLimitKey::ApiKey, Some(authenticator)) => authenticator.authenticate(&request.headers).await.ok(),
+            _ => None,
+        };
+        let key = self.key.extract(&request, identity.as_ref());
+        let decision = {
+            let mut limiters = self.limiters.lock().unwrap_or_else(PoisonError::into_inner);
+            let limiters = &mut *limiters;
+            let full = limiters.clients.len() >= self.max_keys;
+            if full && !limiters.clients.contains_key(&key) && limiters.last_sweep.elapsed() >= self.window {
+                // Sweeping walks every client, so it runs at most once a window
+                limiters.clients.retain(|_, limiter| limiter.current_count() > 0);
+                limiters.last_sweep = Instant::now();
+            }
+            match limiters.clients.get(&key) {
+                Some(limiter) => limiter.check(),
+                None if limiters.clients.len() < self.max_keys => limiters
+                    .clients
+                    .entry(key)
+                    .or_insert_with(|| RateLimiter::new(self.limit, self.window))
+                    .check(),
+                None => limiters.overflow.check(),
+            }
+        };
+
+        match decision {
+            RateLimitDecision::Allowed { .. } => next.run(request).await,
+            RateLimitDecision::Limited { retry_after } => {
+                Response::json(429, r#"{"error":"Too many requests","status":"error"}"#)
+                    .with_header("Retry-After", retry_after.as_secs().to_string())
+            }
+        }
+    }
+}
+
+/// Strip the query string and trailing slashes from a request path.
+fn normalize_path(path: &str) -> &str {
+    let path = path.split('?').next().unwrap_or_default();
+    match path.trim_end_matches('/') {
+        "" => "/",
+        trimmed => trimmed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::ApiKeyAuthenticator;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    struct Record {
+        name: &'static str,
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl Middleware for Record {
+        async fn handle(&self, request: Request, next: Next) -> Response {
+            self.events.lock().unwrap().push(format!("{} before", self.name));
+            let response = next.run(request).await;
+            self.events.lock().unwrap().push(format!("{} after", self.name));
+            response
+        }
+    }
+
+    fn handler(events: Arc<Mutex<Vec<String>>>) -> Handler {
+        Arc::new(move |_request| {
+            events.lock().unwrap().push("handler".to_string());
+            Box::pin(async { Response::json(200, "{}") })
+        })
+    }
+
+    #[tokio::test]
+    async fn test_middleware_runs_in_order() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let stack = MiddlewareStack::new()
+            .layer(Record { name: "outer", events: events.clone() })
+            .layer(Record { name: "inner", events: events.clone() });
+        assert_eq!(stack.len(), 2);
+
+        let response = stack.wrap(handler(events.clone()))(Request::new("GET", "/")).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            *events.lock().unwrap(),
+            ["outer before", "inner before", "handler", "inner after", "outer after"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_require_auth_short_circuits() {
+        let called = Arc::new(AtomicBool::new(false));
+        let handler: Handler = {
+            let called = called.clone();
+            Arc::new(move |_request| {
+                called.store(true, Ordering::SeqCst);
+                Box::pin(async { Response::json(200, "{}") })
+            })
+        };
+        let authenticator = Arc::new(ApiKeyAuthenticator::new(vec!["secret-key".to_string()]));
+        let handler = MiddlewareStack::new()
+            .layer(RequireAuth::new(authenticator, &["/process"]))
+            .wrap(handler);
+
+        for path in ["/process", "/process/", "/process?debug=1"] {
+            let response = handler(Request::new("POST", path)).await;
+            assert_eq!(response.status, 401, "{}", path);
+        }
+        assert!(!called.load(Ordering::SeqCst));
+
+        let mut request = Request::new("POST", "/process");
+        request.headers.insert("X-API-Key", "secret-key");
+        assert_eq!(handler(request).await.status, 200);
+        assert!(called.load(Ordering::SeqCst));
+
+        // Unprotected paths pass straight through
+        called.store(false, Ordering::SeqCst);
+        assert_eq!(handler(Request::new("GET", "/health")).await.status, 200);
+        assert!(called.load(Ordering::SeqCst));
+    }
+
+    struct RejectQuoted;
+
+    #[async_trait]
+    impl Authenticator for RejectQuoted {
+        async fn authenticate(&self, _headers: &crate::http::HeaderMap) -> crate::error::Result<Identity> {
+            Err(crate::error::Error::Auth(r#"bad "token" \ here"#.to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_require_auth_escapes_error() {
+        let handler = MiddlewareStack::new()
+            .layer(RequireAuth::new(Arc::new(RejectQuoted), &["/process"]))
+            .wrap(handler(Arc::new(Mutex::new(Vec::new()))));
+
+        let response = handler(Request::new("POST", "/process")).await;
+        assert_eq!(response.status, 401);
+        let body: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(body["error"], crate::error::Error::Auth(r#"bad "token" \ here"#.to_string()).to_string());
+        assert_eq!(body["status"], "error");
+    }
+
+    fn request_from(peer: &str, headers: &[(&str, &str)]) -> Request {
+        let mut request = Request::new("GET", "/");
+        request.peer = Some(peer.parse().unwrap());
+        for (name, value) in headers {
+            request.headers.insert(*name, *value);
+        }
+        request
+    }
+
+    #[test]
+    fn test_rate_limit_key_peer_ip() {
+        let request = request_from("203.0.113.7:51000", &[("X-Forwarded-For", "198.51.100.1")]);
+        assert_eq!(RateLimitKey::PeerIp.extract(&request, None), "ip:203.0.113.7");
+
+        // The port differs per connection and is not part of the key
+        let request = request_from("[2001:db8::1]:443", &[]);
+        assert_eq!(RateLimitKey::PeerIp.extract(&request, None), "ip:2001:db8::1");
+        assert_eq!(RateLimitKey::PeerIp.extract(&Request::new("GET", "/"), None), "ip:unknown");
+    }
+
+    #[test]
+    fn test_rate_limit_key_header() {
+        let key = RateLimitKey::Header("X-Forwarded-For".to_string());
+        // The rightmost hop was added by the proxy; the client wrote the rest
+        let request = request_from("10.0.0.1:8080", &[("x-forwarded-for", "198.51.100.1, 203.0.113.9")]);
+        assert_eq!(key.extract(&request, None), "header:203.0.113.9");
+        let mut request = request_from("10.0.0.1:8080", &[("X-Forwarded-For", "198.51.100.1")]);
+        request.headers.append("X-Forwarded-For", "203.0.113.9");
+        assert_eq!(key.extract(&request, None), "header:203.0.113.9");
+
+        // A missing or empty header falls back to the peer
+        assert_eq!(key.extract(&request_from("10.0.0.1:8080", &[]), None), "ip:10.0.0.1");
+        let request = request_from("10.0.0.1:8080", &[("X-Forwarded-For", "198.51.100.1, ")]);
+        assert_eq!(key.extract(&request, None), "ip:10.0.0.1");
+    }
+
+    #[test]
+    fn test_rate_limit_key_api_key() {
+        let request = request_from("10.0.0.1:8080", &[("X-API-Key", "key-one")]);
+        let identity = Identity { subject: "api-key:0".to_string(), scheme: "api-key" };
+        assert_eq!(RateLimitKey::ApiKey.extract(&request, Some(&identity)), "user:api-key:0");
+
+        // An unauthenticated key is never trusted as the client's identity
+        assert_eq!(RateLimitKey::ApiKey.extract(&request, None), "ip:10.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_per_key() {
+        let handler = MiddlewareStack::new()
+            .layer(
+                RateLimit::new(RateLimitKey::ApiKey, 2, Duration::from_secs(60))
+                    .with_authenticator(Arc::new(ApiKeyAuthenticator::new(vec!["key-one".into(), "key-two".into()]))),
+            )
+            .wrap(handler(Arc::new(Mutex::new(Vec::new()))));
+
+        let request = || request_from("10.0.0.1:8080", &[("X-API-Key", "key-one")]);
+        assert_eq!(handler(request()).await.status, 200);
+        assert_eq!(handler(request()).await.status, 200);
+        let response = handler(request()).await;
+        assert_eq!(response.status, 429);
+        assert!(response.headers.get("Retry-After").is_some());
+
+        // Another key from the same address has its own budget
+        let request = request_from("10.0.0.1:8080", &[("X-API-Key", "key-two")]);
+        assert_eq!(handler(request).await.status, 200);
+
+        // Invented keys share the budget of their address
+        let request = |key: &str| request_from("10.0.0.2:8080", &[("X-API-Key", key)]);
+        assert_eq!(handler(request("made-up-1")).await.status, 200);
+        assert_eq!(handler(request("made-up-2")).await.status, 200);
+        assert_eq!(handler(request("made-up-3")).await.status, 429);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_caps_tracked_keys() {
+        let rate_limit = Arc::new(RateLimit::new(RateLimitKey::PeerIp, 2, Duration::from_secs(60)).with_max_keys(3));
+        let handler = MiddlewareStack::new()
+            .layer(SharedRateLimit(rate_limit.clone()))
+            .wrap(handler(Arc::new(Mutex::new(Vec::new()))));
+
+        for client in 1..=50 {
+            let response = handler(request_from(&format!("10.0.1.{}:8080", client), &[])).await;
+            assert!(rate_limit.tracked_keys() <= 3);
+            // Clients past the cap share one budget
+            assert_eq!(response.status, if client <= 5 { 200 } else { 429 });
+        }
+        assert_eq!(rate_limit.tracked_keys(), 3);
+
+        // Tracked clients keep their own budget
+        assert_eq!(handler(request_from("10.0.1.1:8080", &[])).await.status, 200);
+        assert_eq!(handler(request_from("10.0.1.1:8080", &[])).await.status, 429);
+    }
+
+    struct SharedRateLimit(Arc<RateLimit>);
+
+    #[async_trait]
+    impl Middleware for SharedRateLimit {
+        async fn handle(&self, request: Request, next: Next) -> Response {
+            self.0.handle(request, next).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_logger_sampling() {
+        let handler: Handler = Arc::new(|request: Request| {
+            let status = if request.path == "/fail" { 500 } else { 200 };
+            Box::pin(async move { Response::json(status, "{}") })
+        });
+        let paths = ["/ok", "/fail", "/ok", "/fail", "/ok"];
+
+        for (sample_rate, expected) in [(0.0, 2), (1.0, 5)] {
+            let (logs, _guard) = crate::testing::LogCapture::install();
+            let handler = MiddlewareStack::new().layer(RequestLogger::sampled(sample_rate)).wrap(handler.clone());
+            for path in paths {
+                handler(Request::new("GET", path)).await;
+            }
+
+            let contents = logs.contents();
+            assert_eq!(contents.matches("Request completed").count(), expected, "{}", contents);
+            assert_eq!(contents.matches("GET /fail HTTP/1.1 -> 500").count(), 2);
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! OpenTelemetry bridge for [`MetricsCollector`], enabled by the `otel` feature.
+
+use std::collections::BTreeMap;
+
+use opentelemetry::metrics::Meter;
+use opentelemetry::KeyValue;
+
+use crate::error::{Error, Result};
+use crate::utils::MetricsCollector;
+
+impl MetricsCollector {
+    /// Register an observable OpenTelemetry instrument for every metric.
+    ///
+    /// Counters become observable counters and gauges observable gauges, read
+    /// from the collector whenever the meter collects. Labeled series share
+    /// their metric's instrument, with the labels as attributes. OTel has no
+    /// pre-aggregated histogram instrument, so each histogram is exported as
+    /// a `<name>.count` counter and a `<name>.sum` gauge. Metrics first
+    /// recorded after this call are not exported.
+    pub fn export_otel(&self, meter: &Meter) -> Result<()> {
+        let snapshot = self.snapshot();
+
+        for (name, series) in group_series(snapshot.counters.keys()) {
+            let collector = self.clone();
+            meter
+                .u64_observable_counter(name)
+                .with_callback(move |observer| {
+                    for (key, attributes) in &series {
+                        observer.observe(collector.get_counter(key), attributes);
+                    }
+                })
+                .try_init()
+                .map_err(otel_error)?;
+        }
+
+        for (name, series) in group_series(snapshot.gauges.keys()) {
+            let collector = self.clone();
+            meter
+                .f64_observable_gauge(name)
+                .with_callback(move |observer| {
+                    for (key, attributes) in &series {
+                        if let Some(value) = collector.get_gauge(key) {
+                            observer.observe(value, attributes);
+                        }
+                    }
+                })
+                .try_init()
+                .map_err(otel_error)?;
+        }
+
+        for name in snapshot.histograms.keys() {
+            let (collector, key) = (self.clone(), name.clone());
+            meter
+                .u64_observable_counter(format!("{}.count", name))
+                .with_callback(move |observer| {
+                    if let Some(histogram) = collector.get_histogram(&key) {
+                        observer.observe(histogram.count, &[]);
+                    }
+                })
+                .try_init()
+                .map_err(otel_error)?;
+
+            let (collector, key) = (self.clone(), name.clone());
+            meter
+                .f64_observable_gauge(format!("{}.sum", name))
+                .with_callback(move |observer| {
+                    if let Some(histogram) = collector.get_histogram(&key) {
+                        observer.observe(histogram.sum, &[]);
+                    }
+                })
+                .try_init()
+                .map_err(otel_error)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn otel_error(error: opentelemetry::metrics::MetricsError) -> Error {
+    Error::Internal(format!("OpenTelemetry instrument: {}", error))
+}
+
+/// Group `name{labels}` series keys by metric name, parsing their labels.
+fn group_series<'a>(
+    keys: impl Iterator<Item = &'a String>,
+) -> BTreeMap<String, Vec<(String, Vec<KeyValue>)>> {
+    let mut grouped: BTreeMap<String, Vec<(String, Vec<KeyValue>)>> = BTreeMap::new();
+    for key in keys {
+        let (name, attributes) = match key.split_once('{') {
+            Some((name, labels)) => (name, parse_labels(labels.strip_suffix('}').unwrap_or(labels))),
+            None => (key.as_str(), Vec::new()),
+        };
+        grouped.entry(name.to_string()).or_default().push((key.clone(), attributes));
+    }
+    grouped
+}
+
+/// Parse `k="v",k2="v2"` as written by the collector, undoing its escapes.
+fn parse_labels(labels: &str) -> Vec<KeyValue> {
+    let mut attributes = Vec::new();
+    let mut rest = labels;
+
+    while let Some((key, after)) = rest.split_once("=\"") {
+        let mut value = String::new();
+        let mut chars = after.char_indices();
+        let mut end = after.len();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, escaped)) => value.push(escaped),
+                    None => {}
+                },
+                '"' => {
+                    end = index + 1;
+                    break;
+                }
+                c => value.push(c),
+            }
+        }
+        attributes.push(KeyValue::new(key.trim_start_matches(',').to_string(), value));
+        rest = &after[end..];
+    }
+
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::any::Any;
+    use std::borrow::Cow;
+    use std::sync::{Arc, Mutex};
+
+    use opentelemetry::metrics::noop::{NoopAsyncInstrument, NoopRegistration};
+    use opentelemetry::metrics::{
+        AsyncInstrument, Callback, CallbackRegistration, InstrumentProvider, ObservableCounter, ObservableGauge,
+    };
+
+    type Observations = Arc<Mutex<Vec<(String, String, Vec<KeyValue>)>>>;
+
+    /// Records created instruments and runs their callbacks straight away.
+    #[derive(Default)]
+    struct Recorder {
+        observations: Observations,
+    }
+
+    struct Observer {
+        instrument: String,
+        observations: Observations,
+    }
+
+    impl<T: ToString> AsyncInstrument<T> for Observer {
+        fn observe(&self, measurement: T, attributes: &[KeyValue]) {
+            self.observations.lock().unwrap().push((
+                self.instrument.clone(),
+                measurement.to_string(),
+                attributes.to_vec(),
+            ));
+        }
+
+        fn as_any(&self) -> Arc<dyn Any> {
+            Arc::new(())
+        }
+    }
+
+    impl Recorder {
+        fn run<T: ToString>(&self, name: &str, callbacks: Vec<Callback<T>>) {
+            let observer = Observer { instrument: name.to_string(), observations: self.observations.clone() };
+            for callback in callbacks {
+                callback(&observer);
+            }
+        }
+    }
+
+    impl InstrumentProvider for Recorder {
+        fn u64_observable_counter(
+            &self,
+            name: Cow<'static, str>,
+            _description: Option<Cow<'static, str>>,
+            _unit: Option<Cow<'static, str>>,
+            callbacks: Vec<Callback<u64>>,
+        ) -> opentelemetry::metrics::Result<ObservableCounter<u64>> {
+            self.run(&name, callbacks);
+            Ok(ObservableCounter::new(Arc::new(NoopAsyncInstrument::new())))
+        }
+
+        fn f64_observable_gauge(
+            &self,
+            name: Cow<'static, str>,
+            _description: Option<Cow<'static, str>>,
+            _unit: Option<Cow<'static, str>>,
+            callbacks: Vec<Callback<f64>>,
+        ) -> opentelemetry::metrics::Result<ObservableGauge<f64>> {
+            self.run(&name, callbacks);
+            Ok(ObservableGauge::new(Arc::new(NoopAsyncInstrument::new())))
+        }
+
+        fn register_callback(
+            &self,
+            _instruments: &[Arc<dyn Any>],
+            _callback: Box<dyn Fn(&dyn opentelemetry::metrics::Observer) + Send + Sync>,
+        ) -> opentelemetry::metrics::Result<Box<dyn CallbackRegistration>> {
+            Ok(Box::new(NoopRegistration::new()))
+        }
+    }
+
+    #[test]
+    fn test_export_otel_creates_instruments() -> Result<()> {
+        let collector = MetricsCollector::new();
+        collector.increment_counter("requests", 3);
+        collector.increment_labeled_counter("requests", &[("route", "/a \"b\"")], 2);
+        collector.set_gauge("cpu_usage", 12.5);
+        collector.observe("latency", 0.25);
+
+        let recorder = Arc::new(Recorder::default());
+        let observations = recorder.observations.clone();
+        collector.export_otel(&Meter::new(recorder))?;
+
+        let mut observations = observations.lock().unwrap().clone();
+        observations.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        assert_eq!(
+            observations,
+            vec![
+                ("cpu_usage".to_string(), "12.5".to_string(), vec![]),
+                ("latency.count".to_string(), "1".to_string(), vec![]),
+                ("latency.sum".to_string(), "0.25".to_string(), vec![]),
+                ("requests".to_string(), "2".to_string(), vec![KeyValue::new("route", "/a \"b\"")]),
+                ("requests".to_string(), "3".to_string(), vec![]),
+            ]
+        );
+        Ok(())
+    }
+}
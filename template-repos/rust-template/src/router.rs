@@ -0,0 +1,215 @@
+//! Request routing by method and path.
+
+use std::future::Future;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::http::{Request, Response};
+use crate::server::Handler;
+
+/// How a path with a trailing slash is matched against routes without one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingSlash {
+    /// `/health/` and `/health` are different paths
+    Strict,
+    /// Answer `/health/` with a 308 redirect to `/health` (the default)
+    #[default]
+    Redirect,
+    /// Serve `/health/` with the `/health` handler
+    Merge,
+}
+
+struct Route {
+    method: String,
+    path: String,
+    handler: Handler,
+}
+
+/// Dispatches requests to handlers by exact method and path.
+///
+/// The query string is ignored when matching. `HEAD` requests without a
+/// route of their own are answered by the `GET` handler, minus the body. Unmatched requests go to the
+/// fallback, a 404 page in HTML or JSON depending on the `Accept` header.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    trailing_slash: TrailingSlash,
+    fallback: Option<Handler>,
+}
+
+impl Router {
+    /// Create a router with no routes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a handler for a method and path.
+    pub fn route<F, Fut>(mut self, method: &str, path: &str, handler: F) -> Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        self.routes.push(Route {
+            method: method.to_string(),
+            path: path.to_string(),
+            handler: Arc::new(move |request| Box::pin(handler(request))),
+        });
+        self
+    }
+
+    /// Handle requests that match no route.
+    pub fn fallback<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        self.fallback = Some(Arc::new(move |request| Box::pin(handler(request))));
+        self
+    }
+
+    /// Set the trailing-slash policy.
+    pub fn trailing_slash(mut self, policy: TrailingSlash) -> Self {
+        self.trailing_slash = policy;
+        self
+    }
+
+    /// Route a request to its handler.
+    pub async fn handle(&self, request: Request) -> Response {
+        if request.method == "HEAD" {
+            self.dispatch(request).await.into_head()
+        } else {
+            self.dispatch(request).await
+        }
+    }
+
+    async fn dispatch(&self, mut request: Request) -> Response {
+        let (path, query) = match request.path.find('?') {
+            Some(index) => request.path.split_at(index),
+            None => (request.path.as_str(), ""),
+        };
+
+        if let Some(route) = self.find(&request.method, path) {
+            return (route.handler)(request).await;
+        }
+
+        if path.len() > 1 && path.ends_with('/') && self.trailing_slash != TrailingSlash::Strict {
+            let trimmed = match path.trim_end_matches('/') {
+                "" => "/",
+                trimmed => trimmed,
+            };
+
+            if let Some(route) = self.find(&request.method, trimmed) {
+                let target = format!("{}{}", trimmed, query);
+                if self.trailing_slash == TrailingSlash::Redirect {
+                    return Response::new(308, "text/plain", "").with_header("Location", target);
+                }
+                request.path = target;
+                return (route.handler)(request).await;
+            }
+        }
+
+        match &self.fallback {
+            Some(fallback) => fallback(request).await,
+            None => not_found(&request),
+        }
+    }
+
+    /// Turn the router into a server request handler.
+    pub fn into_handler(self) -> Handler {
+        let router = Arc::new(self);
+        Arc::new(move |request| {
+            let router = router.clone();
+            Box::pin(async move { router.handle(request).await })
+        })
+    }
+
+    fn find(&self, method: &str, path: &str) -> Option<&Route> {
+        let exact = |method: &str| self.routes.iter().find(|route| route.method == method && route.path == path);
+        match exact(method) {
+            None if method == "HEAD" => exact("GET"),
+            found => found,
+        }
+    }
+}
+
+/// Default 404 response.
+pub fn not_found(request: &Request) -> Response {
+    if request.prefers_json() {
+        Response::json(404, r#"{"error":"Not found","code":"NOT_FOUND","status":"error"}"#)
+    } else {
+        Response::html(404, "<h1>404 Not Found</h1><p>The requested resource was not found.</p>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router(policy: TrailingSlash) -> Router {
+        Router::new()
+            .trailing_slash(policy)
+            .route("GET", "/health", |request: Request| async move {
+                Response::json(200, format!(r#"{{"path":"{}"}}"#, request.path))
+            })
+    }
+
+    #[tokio::test]
+    async fn test_exact_match_ignores_query() {
+        let response = router(TrailingSlash::Strict).handle(Request::new("GET", "/health?verbose=1")).await;
+        assert_eq!(response.status, 200);
+
+        let response = router(TrailingSlash::Strict).handle(Request::new("POST", "/health")).await;
+        assert_eq!(response.status, 404);
+    }
+
+    #[tokio::test]
+    async fn test_not_found_fallback() {
+        let mut request = Request::new("GET", "/missing");
+        request.headers.insert("Accept", "application/json");
+        let response = router(TrailingSlash::Strict).handle(request).await;
+        assert_eq!(response.status, 404);
+        assert_eq!(response.headers.get("Content-Type"), Some("application/json"));
+
+        let router = router(TrailingSlash::Strict)
+            .fallback(|_request| async { Response::new(404, "text/plain", "nothing here") });
+        let response = router.handle(Request::new("GET", "/missing")).await;
+        assert_eq!(response.body, b"nothing here");
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash_strict() {
+        let response = router(TrailingSlash::Strict).handle(Request::new("GET", "/health/")).await;
+        assert_eq!(response.status, 404);
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash_redirect() {
+        let response = router(TrailingSlash::Redirect).handle(Request::new("GET", "/health/?verbose=1")).await;
+        assert_eq!(response.status, 308);
+        assert_eq!(response.headers.get("Location"), Some("/health?verbose=1"));
+
+        let response = router(TrailingSlash::Redirect).handle(Request::new("GET", "/missing/")).await;
+        assert_eq!(response.status, 404);
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash_merge() {
+        let response = router(TrailingSlash::Merge).handle(Request::new("GET", "/health//")).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, br#"{"path":"/health"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_head_uses_get_handler() {
+        let response = router(TrailingSlash::Strict).handle(Request::new("HEAD", "/health")).await;
+        assert_eq!(response.status, 200);
+        assert!(response.body.is_empty());
+        assert!(response.to_http_string().contains("Content-Length: 18\r\n"));
+
+        let response = router(TrailingSlash::Strict).handle(Request::new("HEAD", "/missing")).await;
+        assert_eq!(response.status, 404);
+        assert!(response.body.is_empty());
+    }
+}
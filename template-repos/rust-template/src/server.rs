@@ -0,0 +1,2258 @@
+//! Minimal HTTP server.
+
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{timeout, Instant};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use crate::auth::{ApiKeyAuthenticator, Authenticator};
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::health::HealthStatus;
+use crate::http::{
+    read_request_body, read_request_head, BodyReader, BodyStream, Request, RequestLimits, Response,
+    ShutdownSignal, TraceContext, NDJSON,
+};
+use crate::metrics::MetricsFormat;
+use crate::middleware::{
+    ensure_request_id, Middleware, MiddlewareStack, RateLimit, RequestId, RequestLogger, RequireAuth,
+};
+use crate::process_data;
+use crate::router::Router;
+use crate::tls;
+use crate::utils::{
+    constant_time_eq, decode_basic_auth, parse_authorization, BlockingPool, HealthChecker, MetricsCollector,
+    MetricsPusher, Uptime,
+};
+
+/// Request handler invoked for every parsed request.
+pub type Handler = Arc<dyn Fn(Request) -> BoxFuture<'static, Response> + Send + Sync>;
+
+/// HTTP server.
+pub struct Server {
+    state: AppState,
+    handler: Option<Handler>,
+    root: Option<Handler>,
+    not_found: Option<Handler>,
+    authenticator: Option<Arc<dyn Authenticator>>,
+    middleware: MiddlewareStack,
+    metrics_pusher: Option<MetricsPusher>,
+}
+
+impl Server {
+    /// Create a server using the default routes.
+    pub fn new(config: Config) -> Self {
+        let health_pool = BlockingPool::new(config.server.health_check_workers);
+        let metrics = MetricsCollector::with_max_label_sets(config.observability.max_label_sets);
+
+        Self {
+            state: AppState {
+                config,
+                health: HealthChecker::new(),
+                health_pool,
+                metrics,
+                uptime: Uptime::new(),
+            },
+            handler: None,
+            root: None,
+            not_found: None,
+            authenticator: None,
+            middleware: MiddlewareStack::new(),
+            metrics_pusher: None,
+        }
+    }
+
+    /// Replace the default routes with a custom request handler.
+    pub fn with_handler<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        self.handler = Some(Arc::new(move |request| Box::pin(handler(request))));
+        self
+    }
+
+    /// Serve `GET /` from this handler instead of the built-in page.
+    pub fn with_root<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        self.root = Some(Arc::new(move |request| Box::pin(handler(request))));
+        self
+    }
+
+    /// Answer unmatched requests from this handler instead of the built-in 404 page.
+    pub fn with_not_found<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        self.not_found = Some(Arc::new(move |request| Box::pin(handler(request))));
+        self
+    }
+
+    /// Require authentication for the processing endpoints.
+    pub fn with_authenticator(mut self, authenticator: Arc<dyn Authenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    /// Run a middleware around every request.
+    ///
+    /// Middleware runs in the order added, inside the built-in request
+    /// logging and authentication.
+    pub fn with_middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware = self.middleware.layer(middleware);
+        self
+    }
+
+    /// Run these checks for `/health`.
+    pub fn with_health_checker(mut self, checker: HealthChecker) -> Self {
+        self.state.health = checker;
+        self
+    }
+
+    /// Record server metrics in this collector.
+    pub fn with_metrics(mut self, metrics: MetricsCollector) -> Self {
+        self.state.metrics = metrics;
+        self
+    }
+
+    /// Shut this pusher down with the server, after open connections drain,
+    /// so its final push includes their metrics.
+    ///
+    /// Replaces the pusher `observability.metrics_push_url` would start.
+    pub fn with_metrics_pusher(mut self, pusher: MetricsPusher) -> Self {
+        self.metrics_pusher = Some(pusher);
+        self
+    }
+
+    /// Report uptime measured from this starting point.
+    pub fn with_uptime(mut self, uptime: Uptime) -> Self {
+        self.state.uptime = uptime;
+        self
+    }
+
+    /// Bind to the configured address and serve connections until Ctrl-C.
+    pub async fn run(self) -> Result<()> {
+        info!(
+            name = env!("CARGO_PKG_NAME"),
+            version = env!("CARGO_PKG_VERSION"),
+            "Starting server"
+        );
+        self.state.config.log_summary();
+
+        let address = self.state.config.server_address();
+        info!("Starting HTTP server on {}", address);
+
+        let listener = TcpListener::bind(&address).await
+            .map_err(|e| Error::Network(format!("Failed to bind to {}: {}", address, e)))?;
+
+        info!("Server listening on {}", address);
+
+        let dump_handler = self.state.config.observability.metrics_dump_dir.clone()
+            .and_then(|dir| spawn_metrics_dump_handler(self.state.metrics.clone(), dir));
+
+        let result = self.serve_with_shutdown(listener, async {
+            if let Err(e) = tokio::signal::ctrl_c().await {
+                error!("Failed to listen for shutdown signal: {}", e);
+                std::future::pending::<()>().await;
+            }
+            info!("Shutdown signal received");
+        })
+        .await;
+
+        if let Some(handle) = dump_handler {
+            handle.abort();
+        }
+        result
+    }
+
+    /// Serve connections from an already bound listener.
+    pub async fn serve(self, listener: TcpListener) -> Result<()> {
+        self.serve_with_shutdown(listener, std::future::pending()).await
+    }
+
+    /// Serve connections until `shutdown` completes, then drain open connections.
+    ///
+    /// Connections are served over TLS when `server.tls_enabled` is set.
+    /// Draining waits up to `server.shutdown_grace_period` before aborting
+    /// whatever is still running.
+    pub async fn serve_with_shutdown(
+        self,
+        listener: TcpListener,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<()> {
+        let tls = match self.state.config.server.tls_enabled {
+            true => Some(tls::acceptor(&self.state.config.server)?),
+            false => None,
+        };
+        let state = Arc::new(self.state);
+        // A pusher given in code takes the place of the configured one
+        let observability = &state.config.observability;
+        let metrics_pusher = self.metrics_pusher.or_else(|| {
+            observability.metrics_push_url.clone().map(|url| {
+                let block_private = state.config.security.block_private_outbound;
+                let interval = observability.metrics_push_interval;
+                state.metrics.spawn_http_pusher(interval, observability.metrics_format, url, block_private)
+            })
+        });
+        let (root, not_found) = (self.root, self.not_found);
+        let uses_default_routes = self.handler.is_none();
+        let handler = self.handler.unwrap_or_else(|| {
+            let mut router = default_router(&state, root);
+            if let Some(not_found) = not_found {
+                router = router.fallback(move |request| not_found(request));
+            }
+            router.into_handler()
+        });
+
+        let mut middleware = MiddlewareStack::new()
+            .layer(RequestId::new(state.config.server.request_id_header.clone()))
+            .layer(RequestLogger::sampled(state.config.logging.access_log_sample_rate));
+        let security = &state.config.security;
+        if security.rate_limiting_enabled {
+            let limit = security.rate_limit_rpm as usize;
+            let window = Duration::from_secs(60);
+            let rate_limit = RateLimit::new(security.rate_limit_key.clone(), limit, window)
+                .with_authenticator(Arc::new(ApiKeyAuthenticator::from_config(security)));
+            middleware = middleware.layer(rate_limit);
+        }
+        if let (true, Some(authenticator)) = (uses_default_routes, self.authenticator) {
+            middleware = middleware.layer(RequireAuth::new(authenticator, &["/process", "/process-stream"]));
+        }
+        let handler = middleware.extend(self.middleware).wrap(handler);
+        install_panic_location_hook();
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let service = Arc::new(Service { state, handler, shutdown: shutdown_rx, tls });
+        let mut connections = JoinSet::new();
+
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                accepted = listener.accept() => match accepted {
+                    Ok((socket, addr)) => {
+                        info!("New connection from {}", addr);
+                        let accepted = Instant::now();
+
+                        let service = service.clone();
+                        let active = ActiveConnection::open(&service.state.metrics);
+                        connections.spawn(async move {
+                            let _active = active;
+                            // Small enough that reads past the PROXY header skip this buffer
+                            let mut socket = BufReader::with_capacity(MAX_PROXY_HEADER_BYTES, socket);
+                            let addr = service.client_addr(&mut socket, addr).await;
+                            let result = match &service.tls {
+                                Some(acceptor) => match service.accept_tls(acceptor, socket).await {
+                                    Ok(stream) => service.handle_connection(stream, addr, accepted).await,
+                                    Err(e) => Err(e),
+                                },
+                                None => service.handle_connection(socket, addr, accepted).await,
+                            };
+                            if let Err(e) = result {
+                                e.log_with(&format!("Error handling connection from {}", addr));
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        error!("Failed to accept connection: {}", e);
+                    }
+                },
+                // Reap finished connections so the set doesn't grow unbounded
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            }
+        }
+
+        drop(listener);
+        let _ = shutdown_tx.send(true);
+        service.drain(connections).await;
+        if let Some(pusher) = metrics_pusher {
+            pusher.shutdown().await;
+        }
+        Ok(())
+    }
+}
+
+/// Dump metrics to `dir` every time the process receives SIGUSR1.
+#[cfg(unix)]
+fn spawn_metrics_dump_handler(metrics: MetricsCollector, dir: PathBuf) -> Option<JoinHandle<()>> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("Failed to listen for SIGUSR1, metrics dumps are disabled: {}", e);
+            return None;
+        }
+    };
+    info!("Send SIGUSR1 to dump metrics to {}", dir.display());
+
+    Some(tokio::spawn(async move {
+        while signals.recv().await.is_some() {
+            if let Err(e) = metrics.dump_json(&dir) {
+                e.log_with("Failed to dump metrics");
+            }
+        }
+    }))
+}
+
+#[cfg(not(unix))]
+fn spawn_metrics_dump_handler(_metrics: MetricsCollector, dir: PathBuf) -> Option<JoinHandle<()>> {
+    warn!("Metrics dumps to {} need SIGUSR1, which this platform lacks", dir.display());
+    None
+}
+
+/// Tracks one open connection in the `active_connections` gauge.
+struct ActiveConnection {
+    metrics: MetricsCollector,
+}
+
+impl ActiveConnection {
+    const GAUGE: &'static str = "active_connections";
+
+    fn open(metrics: &MetricsCollector) -> Self {
+        metrics.adjust_gauge(Self::GAUGE, 1.0);
+        Self { metrics: metrics.clone() }
+    }
+}
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.metrics.adjust_gauge(Self::GAUGE, -1.0);
+    }
+}
+
+/// Per-listener state shared by every connection.
+struct Service {
+    state: Arc<AppState>,
+    handler: Handler,
+    shutdown: watch::Receiver<bool>,
+    tls: Option<TlsAcceptor>,
+}
+
+impl Service {
+    /// Wait for open connections to finish, logging progress every second.
+    async fn drain(&self, mut connections: JoinSet<()>) {
+        let grace_period = self.state.config.server.shutdown_grace_period;
+        let deadline = tokio::time::sleep(grace_period);
+        let mut progress = tokio::time::interval(Duration::from_secs(1));
+        tokio::pin!(deadline);
+
+        while !connections.is_empty() {
+            tokio::select! {
+                _ = connections.join_next() => {}
+                _ = progress.tick() => {
+                    info!(remaining = connections.len(), "Draining connections");
+                }
+                _ = &mut deadline => {
+                    let forced = connections.len();
+                    connections.abort_all();
+                    while connections.join_next().await.is_some() {}
+                    warn!(
+                        forced,
+                        "Shutdown grace period of {:?} expired, forced {} connections",
+                        grace_period,
+                        forced
+                    );
+                    return;
+                }
+            }
+        }
+
+        info!("All connections drained");
+    }
+
+    /// Apply the configured default charset to a response.
+    fn with_charset(&self, response: Response) -> Response {
+        if self.state.config.server.default_charset {
+            response.with_default_charset()
+        } else {
+            response
+        }
+    }
+
+    /// Record how long a client took to send its first request head.
+    ///
+    /// Clients slower than `slow_client_threshold` are logged and counted, as
+    /// dribbling bytes to hold connections open is how slowloris attacks work.
+    fn record_first_request(&self, peer: SocketAddr, accepted: Instant) {
+        let elapsed = accepted.elapsed();
+        let metrics = &self.state.metrics;
+        metrics.observe("time_to_first_request_seconds", elapsed.as_secs_f64());
+
+        let threshold = self.state.config.server.slow_client_threshold;
+        if elapsed > threshold {
+            metrics.increment_counter("slow_clients_total", 1);
+            warn!(%peer, ?elapsed, ?threshold, "Slow client took {:?} to send its first request", elapsed);
+        }
+    }
+
+    /// The client's address: from a PROXY protocol header when enabled, else the socket peer.
+    ///
+    /// A missing, malformed or slow header falls back to the socket peer.
+    async fn client_addr(&self, socket: &mut BufReader<TcpStream>, peer: SocketAddr) -> SocketAddr {
+        if !self.state.config.server.proxy_protocol {
+            return peer;
+        }
+
+        let wait = PROXY_HEADER_TIMEOUT;
+        match timeout(wait, read_proxy_header(socket)).await {
+            Ok(Ok(Some(client))) => {
+                debug!(%peer, %client, "Client address from PROXY header");
+                client
+            }
+            Ok(Ok(None)) => peer,
+            Ok(Err(e)) => {
+                warn!(%peer, "Ignoring PROXY header: {}", e);
+                peer
+            }
+            Err(_) => {
+                debug!(%peer, "No PROXY header within {:?}", wait);
+                peer
+            }
+        }
+    }
+
+    /// Complete the TLS handshake, allowing as long as a new connection may idle.
+    async fn accept_tls<S>(&self, acceptor: &TlsAcceptor, socket: S) -> Result<tokio_rustls::server::TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let handshake_timeout = self.state.config.server.idle_connection_timeout;
+        timeout(handshake_timeout, acceptor.accept(socket))
+            .await
+            .map_err(|_| Error::Timeout(format!("TLS handshake exceeded {:?}", handshake_timeout)))?
+            .map_err(|e| Error::Network(format!("TLS handshake failed: {}", e)))
+    }
+
+    /// Serve requests on a connection until it is closed or goes idle.
+    async fn handle_connection<S>(&self, socket: S, peer: SocketAddr, accepted: Instant) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (reader, mut writer) = tokio::io::split(socket);
+        let mut reader = BufReader::new(reader);
+        let result = self.serve_requests(&mut reader, &mut writer, peer, accepted).await;
+
+        // TLS clients need a close_notify to tell a finished connection from a cut one
+        let _ = writer.shutdown().await;
+        result
+    }
+
+    async fn serve_requests<R, W>(
+        &self,
+        mut reader: &mut BufReader<R>,
+        mut writer: W,
+        peer: SocketAddr,
+        accepted: Instant,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let limits = RequestLimits::from_config(&self.state.config.server);
+        let mut shutdown = self.shutdown.clone();
+        let mut served = 0usize;
+        let mut pipelined = 0usize;
+
+        loop {
+            // Wait for the next request to start, closing idle connections
+            let idle_timeout = if served == 0 {
+                self.state.config.server.idle_connection_timeout
+            } else {
+                self.state.config.server.keep_alive_timeout
+            };
+
+            tokio::select! {
+                ready = timeout(idle_timeout, reader.fill_buf()) => match ready {
+                    Err(_) => {
+                        debug!("Closing connection idle for {:?}", idle_timeout);
+                        return Ok(());
+                    }
+                    Ok(Ok([])) => return Ok(()),
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => {
+                        return Err(Error::Network(format!("Failed to read from socket: {}", e)));
+                    }
+                },
+                _ = shutdown.wait_for(|&stopping| stopping) => {
+                    debug!("Closing idle connection for shutdown");
+                    return Ok(());
+                }
+            }
+
+            let read = async {
+                let Some(mut request) = read_request_head(&mut reader, &limits).await? else {
+                    return Ok(None);
+                };
+                if served == 0 {
+                    self.record_first_request(peer, accepted);
+                }
+                // Clients sending `Expect: 100-continue` hold the body until told to proceed
+                if request.expects_continue() {
+                    writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+                    writer.flush().await?;
+                }
+                // Streamed bodies are fed to the handler while it runs
+                let body = if request.streams_body() {
+                    let (sender, receiver) = mpsc::channel(BODY_STREAM_CHUNKS);
+                    request.body_stream = BodyStream::new(receiver);
+                    // Handlers consume these as they arrive, so they aren't held to the buffered limit
+                    Some((BodyReader::for_request(&request, usize::MAX)?, sender))
+                } else {
+                    read_request_body(&mut reader, &mut request, &limits).await?;
+                    None
+                };
+                request.shutdown = ShutdownSignal::new(self.shutdown.clone());
+                request.peer = Some(peer);
+                Ok::<_, Error>(Some((request, body)))
+            };
+
+            let (request, body) = match timeout(self.state.config.server.idle_connection_timeout, read).await {
+                Err(_) => {
+                    debug!("Closing connection that stalled mid-request");
+                    return Ok(());
+                }
+                Ok(Ok(Some(request))) => request,
+                Ok(Ok(None)) => return Ok(()),
+                Ok(Err(e)) => {
+                    let response = match e {
+                        Error::UriTooLong(_)
+                        | Error::HeaderTooLarge(_)
+                        | Error::PayloadTooLarge(_)
+                        | Error::NotImplemented(_) => {
+                            error_response(&e)
+                        }
+                        _ => Response::json(400, r#"{"error":"Malformed request","status":"error"}"#),
+                    };
+                    let response = self.with_charset(response).with_header("Connection", "close");
+                    let _ = writer.write_all(&response.to_http_bytes()).await;
+                    return Err(e);
+                }
+            };
+
+            // Requests are answered one at a time; bound how many a client queues up
+            pipelined = if body.is_some() || reader.buffer().is_empty() { 0 } else { pipelined + 1 };
+            let flooded = pipelined >= self.state.config.server.max_pipelined_requests;
+            if flooded {
+                warn!(%peer, pipelined, "Closing connection with too many pipelined requests");
+            }
+
+            let trace = TraceContext::from_request(&request);
+            let span = info_span!(
+                "request",
+                method = %request.method,
+                path = %request.path,
+                trace_id = %trace.trace_id,
+                span_id = %trace.span_id,
+                request_id = tracing::field::Empty,
+            );
+
+            let client_keep_alive = request.wants_keep_alive();
+            let version = request.version.clone();
+            let origin = request.header("Origin").map(str::to_string);
+            let is_head = request.method == "HEAD";
+
+            let request_timeout = self.state.config.server.request_timeout;
+            let preflight = preflight_response(&self.state.config, &request);
+            let respond = async {
+                let response = async {
+                    if let Some(response) = preflight {
+                        return response;
+                    }
+                    let request_id_header = &self.state.config.server.request_id_header;
+                    match timeout(request_timeout, call_handler(&self.handler, request, request_id_header)).await {
+                        Ok(response) => response,
+                        Err(_) => {
+                            let error = Error::Timeout(format!("Request exceeded {:?}", request_timeout));
+                            error.log();
+                            error_response(&error)
+                        }
+                    }
+                }
+                .instrument(span)
+                .await;
+
+                // Responses to HEAD never carry a body, whatever the handler returned
+                let response = if is_head { response.into_head() } else { response };
+
+                let response = response.with_version(&version);
+
+                // Finish in-flight requests during shutdown but don't accept more. An
+                // unchunked stream is only delimited by the connection closing.
+                let keep_alive = client_keep_alive
+                    && !flooded
+                    && !*shutdown.borrow()
+                    && (response.is_chunked() || !response.is_streamed());
+                let response = self.with_charset(apply_cors(&self.state.config, origin.as_deref(), response))
+                    .with_header("traceparent", trace.to_header())
+                    .with_header("Connection", if keep_alive { "keep-alive" } else { "close" });
+
+                write_response(&mut writer, response).await
+                    .map_err(|e| Error::Network(format!("Failed to write response: {}", e)))?;
+                Ok::<_, Error>(keep_alive)
+            };
+            let pump = async {
+                match body {
+                    Some((body_reader, sender)) => {
+                        let idle_timeout = self.state.config.server.idle_connection_timeout;
+                        pump_body(&mut reader, body_reader, sender, idle_timeout).await
+                    }
+                    None => Ok(()),
+                }
+            };
+            let (keep_alive, pumped) = tokio::join!(respond, pump);
+            let keep_alive = keep_alive?;
+            if let Err(e) = pumped {
+                // The rest of the body is unread, so the connection can't be reused
+                debug!(%peer, "Closing connection after a failed streamed body: {}", e);
+                return Ok(());
+            }
+
+            served += 1;
+            if !keep_alive {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Longest PROXY protocol v1 header, including its CRLF.
+const MAX_PROXY_HEADER_BYTES: usize = 107;
+
+/// How long a new connection may take to send its PROXY header.
+///
+/// The load balancer sends it as soon as it connects, so a short wait is enough.
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Read a PROXY protocol v1 header from the start of a connection.
+///
+/// Load balancers send the header in one packet, so the first bytes read
+/// decide whether there is one. Data that doesn't start with `PROXY ` is left
+/// in the buffer for the HTTP parser. Returns `None` for no header or an
+/// `UNKNOWN` one.
+async fn read_proxy_header<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<SocketAddr>> {
+    const PREFIX: &[u8] = b"PROXY ";
+    let buffered = reader.fill_buf().await?;
+    if buffered.is_empty() || !buffered.starts_with(&PREFIX[..buffered.len().min(PREFIX.len())]) {
+        return Ok(None);
+    }
+
+    // Consume the header whether or not it parses
+    let mut line = Vec::with_capacity(MAX_PROXY_HEADER_BYTES);
+    tokio::io::AsyncReadExt::take(&mut *reader, MAX_PROXY_HEADER_BYTES as u64)
+        .read_until(b'\n', &mut line)
+        .await?;
+    match line.strip_suffix(b"\r\n") {
+        Some(line) => parse_proxy_header(&String::from_utf8_lossy(line)),
+        None if line.len() == MAX_PROXY_HEADER_BYTES => {
+            Err(Error::BadRequest("PROXY header is too long".to_string()))
+        }
+        None => Err(Error::BadRequest("PROXY header is not terminated by CRLF".to_string())),
+    }
+}
+
+/// Parse a PROXY protocol v1 line, e.g. `PROXY TCP4 203.0.113.7 10.0.0.1 51234 443`.
+fn parse_proxy_header(line: &str) -> Result<Option<SocketAddr>> {
+    let malformed = || Error::BadRequest(format!("Malformed PROXY header: {:?}", line));
+    let mut fields = line.split(' ');
+    if fields.next() != Some("PROXY") {
+        return Err(malformed());
+    }
+
+    let protocol = fields.next().ok_or_else(malformed)?;
+    if protocol == "UNKNOWN" {
+        return Ok(None);
+    }
+    let (Some(source), Some(_destination), Some(source_port), Some(_destination_port), None) =
+        (fields.next(), fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        return Err(malformed());
+    };
+
+    let ip: std::net::IpAddr = source.parse().map_err(|_| malformed())?;
+    let port: u16 = source_port.parse().map_err(|_| malformed())?;
+    match (protocol, ip) {
+        ("TCP4", std::net::IpAddr::V4(_)) | ("TCP6", std::net::IpAddr::V6(_)) => Ok(Some(SocketAddr::new(ip, port))),
+        _ => Err(malformed()),
+    }
+}
+
+/// Chunks of a streamed request body buffered ahead of the handler.
+const BODY_STREAM_CHUNKS: usize = 16;
+
+/// Feed a streamed request body to the handler as it arrives.
+///
+/// Reading continues after the handler stops listening, so the connection
+/// stays in step for the next request.
+async fn pump_body<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    mut body_reader: BodyReader,
+    sender: mpsc::Sender<Result<Vec<u8>>>,
+    idle_timeout: Duration,
+) -> Result<()> {
+    loop {
+        let chunk = match timeout(idle_timeout, body_reader.next_chunk(reader)).await {
+            Ok(chunk) => chunk,
+            Err(_) => Err(Error::Timeout("Request body stalled".to_string())),
+        };
+        match chunk {
+            Ok(Some(chunk)) => {
+                let _ = sender.send(Ok(chunk)).await;
+            }
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                let _ = sender.send(Err(Error::BadRequest(e.to_string()))).await;
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// Write a response, sending a streamed body chunk by chunk as it is produced.
+async fn write_response<W: AsyncWrite + Unpin>(writer: &mut W, mut response: Response) -> std::io::Result<()> {
+    writer.write_all(&response.to_http_bytes()).await?;
+    let chunked = response.is_chunked();
+    if let Some(mut chunks) = response.take_stream() {
+        writer.flush().await?;
+        while let Some(chunk) = chunks.recv().await {
+            if !chunked {
+                writer.write_all(chunk.as_bytes()).await?;
+            } else if !chunk.is_empty() {
+                // An empty chunk would end the body early
+                writer.write_all(format!("{:x}\r\n{}\r\n", chunk.len(), chunk).as_bytes()).await?;
+            }
+            writer.flush().await?;
+        }
+        if chunked {
+            writer.write_all(b"0\r\n\r\n").await?;
+        }
+    }
+    writer.flush().await
+}
+
+/// Run the handler, converting a panic into a 500 response.
+///
+/// The request is given its ID up front so a panic can be logged with it,
+/// along with the request line and where the panic happened.
+async fn call_handler(handler: &Handler, mut request: Request, request_id_header: &str) -> Response {
+    let request_id = ensure_request_id(&mut request, request_id_header);
+    let (method, path) = (request.method.clone(), request.path.clone());
+
+    match AssertUnwindSafe(async { handler(request).await }).catch_unwind().await {
+        Ok(response) => response,
+        Err(payload) => {
+            let location = PANIC_LOCATION.with(|location| location.borrow_mut().take());
+            error!(
+                request_id = %request_id,
+                method = %method,
+                path = %path,
+                location = location.as_deref().unwrap_or("unknown"),
+                code = "INTERNAL_ERROR",
+                "Handler panicked: {}",
+                panic_message(&*payload)
+            );
+            Response::json(500, r#"{"error":"Internal server error","status":"error"}"#)
+                .with_header(request_id_header, request_id)
+        }
+    }
+}
+
+thread_local! {
+    /// Where the last panic on this thread happened, set by the hook below.
+    static PANIC_LOCATION: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Record panic locations for [`call_handler`], keeping the existing hook's output.
+///
+/// A caught panic's payload doesn't say where it came from, so the hook
+/// notes it for the handler's thread. Installed once per process.
+fn install_panic_location_hook() {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let location = info.location().map(|location| location.to_string());
+            PANIC_LOCATION.with(|slot| *slot.borrow_mut() = location);
+            previous(info);
+        }));
+    });
+}
+
+/// Allow cross-origin access to the response if the request's origin is allowed.
+fn apply_cors(config: &Config, origin: Option<&str>, response: Response) -> Response {
+    match origin {
+        Some(origin) if config.security.cors_enabled && config.security.is_origin_allowed(origin) => {
+            response
+                .with_header("Access-Control-Allow-Origin", origin)
+                .with_header("Vary", "Origin")
+        }
+        _ => response,
+    }
+}
+
+/// Answer a CORS preflight from an allowed origin without calling the handler.
+fn preflight_response(config: &Config, request: &Request) -> Option<Response> {
+    let security = &config.security;
+    let is_preflight = request.method == "OPTIONS"
+        && request.header("Access-Control-Request-Method").is_some()
+        && request.header("Origin").is_some_and(|origin| security.is_origin_allowed(origin));
+    if !security.cors_enabled || !is_preflight {
+        return None;
+    }
+
+    Some(
+        Response::new(204, "text/plain", "")
+            .with_header("Access-Control-Allow-Methods", security.cors_methods.join(", "))
+            .with_header("Access-Control-Allow-Headers", security.cors_allowed_headers.join(", ")),
+    )
+}
+
+/// Build a JSON error response with the error's status code.
+fn error_response(error: &Error) -> Response {
+    let body = serde_json::json!({
+        "error": error.to_string(),
+        "code": error.code(),
+        "status": "error",
+    });
+    Response::json(error.status_code(), body.to_string())
+}
+
+/// Extract a readable message from a panic payload.
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// State shared by the default routes.
+struct AppState {
+    config: Config,
+    health: HealthChecker,
+    health_pool: BlockingPool,
+    metrics: MetricsCollector,
+    uptime: Uptime,
+}
+
+/// Default routes.
+fn default_router(state: &Arc<AppState>, root: Option<Handler>) -> Router {
+    let root = root.unwrap_or_else(|| {
+        Arc::new(|_request| {
+            Box::pin(async {
+                Response::html(200, "<h1>Hello from Rust Server!</h1><p>Server is running.</p>")
+            })
+        })
+    });
+
+    let router = Router::new()
+        .trailing_slash(state.config.server.trailing_slash)
+        .route("GET", "/", move |request| root(request))
+        .route("GET", "/health", with_state(state, health))
+        .route("POST", "/process", process)
+        .route("POST", "/process-stream", with_state(state, process_stream));
+
+    let observability = &state.config.observability;
+    if observability.metrics_enabled {
+        router.route("GET", &observability.metrics_path, with_state(state, metrics))
+    } else {
+        router
+    }
+}
+
+/// Adapt a handler that needs the shared state into a route handler.
+fn with_state<F, Fut>(state: &Arc<AppState>, handler: F) -> impl Fn(Request) -> Fut + Send + Sync + 'static
+where
+    F: Fn(Arc<AppState>, Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    let state = state.clone();
+    move |request| handler(state.clone(), request)
+}
+
+/// Health report as JSON, or a one-word status for clients preferring `text/plain`.
+async fn health(state: Arc<AppState>, request: Request) -> Response {
+    let report = state.health.run_all_on(&state.health_pool).await;
+    let health = report.status();
+    let status = if health == HealthStatus::Healthy { 200 } else { 503 };
+
+    if request.accept_quality("text/plain") > request.accept_quality("application/json") {
+        return Response::new(status, "text/plain", health.as_text());
+    }
+    let body = serde_json::json!({
+        "status": health,
+        "score": report.score(),
+        "timestamp": crate::utils::current_timestamp(),
+        "checks": report.checks,
+    });
+    Response::json(status, body.to_string())
+}
+
+async fn process(request: Request) -> Response {
+    let input = match request.body_str() {
+        Ok(input) => input,
+        Err(e) => return error_response(&e),
+    };
+
+    match process_data(input) {
+        Ok(result) => {
+            Response::json(200, format!(r#"{{"result":"{}","status":"success"}}"#, result))
+        }
+        Err(e) => {
+            Response::json(400, format!(r#"{{"error":"{}","status":"error"}}"#, e))
+        }
+    }
+}
+
+/// Process each line of an NDJSON body, streaming back one result per line.
+///
+/// Lines are JSON strings or objects with a string `input`. A line that
+/// can't be parsed or processed gets an error object and the stream goes on.
+/// A line longer than `server.max_body_bytes` gets a `PAYLOAD_TOO_LARGE`
+/// error object and ends the stream, since the status is already sent.
+async fn process_stream(state: Arc<AppState>, mut request: Request) -> Response {
+    let mut body = request.take_body_stream();
+    let (sender, receiver) = mpsc::channel(BODY_STREAM_CHUNKS);
+    // Each line is buffered until its newline arrives, so hold lines to the buffered body limit
+    let max_line_bytes = state.config.server.max_body_bytes;
+    let too_long = move |line_number| {
+        let error = Error::PayloadTooLarge(format!("Line exceeds {} bytes", max_line_bytes));
+        format!("{}\n", error_record(line_number, &error))
+    };
+
+    tokio::spawn(async move {
+        let mut pending = Vec::new();
+        let mut line_number = 0;
+        loop {
+            let chunk = match body.next_chunk().await {
+                Some(Ok(chunk)) => Some(chunk),
+                Some(Err(e)) => {
+                    let _ = sender.send(format!("{}\n", error_record(line_number + 1, &e))).await;
+                    return;
+                }
+                None => None,
+            };
+            let finished = chunk.is_none();
+            pending.extend_from_slice(&chunk.unwrap_or_default());
+
+            while let Some(end) = next_line_end(&pending, finished) {
+                line_number += 1;
+                if end > max_line_bytes {
+                    let _ = sender.send(too_long(line_number)).await;
+                    return;
+                }
+                let line: Vec<u8> = pending.drain(..=end).collect();
+                if let Some(record) = process_ndjson_line(&line, line_number) {
+                    if sender.send(format!("{}\n", record)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+
+            if finished {
+                return;
+            }
+            if pending.len() > max_line_bytes {
+                // The rest of the body is left for the server to discard
+                let _ = sender.send(too_long(line_number + 1)).await;
+                return;
+            }
+        }
+    });
+
+    Response::stream(200, NDJSON, receiver)
+}
+
+/// Index of the last byte of the first complete line in `pending`.
+///
+/// Once the body has `finished`, a last line without a newline counts too.
+fn next_line_end(pending: &[u8], finished: bool) -> Option<usize> {
+    pending
+        .iter()
+        .position(|&b| b == b'\n')
+        .or_else(|| (finished && !pending.is_empty()).then(|| pending.len() - 1))
+}
+
+/// Process one NDJSON line, or `None` for a blank line.
+fn process_ndjson_line(line: &[u8], line_number: usize) -> Option<serde_json::Value> {
+    let line = String::from_utf8_lossy(line);
+    let line = line.trim();
+    if line.is_empty() {
+        return None;
+    }
+
+    let input = match serde_json::from_str(line) {
+        Ok(serde_json::Value::String(input)) => Ok(input),
+        Ok(serde_json::Value::Object(mut object)) => match object.remove("input") {
+            Some(serde_json::Value::String(input)) => Ok(input),
+            _ => Err(Error::BadRequest("Expected a string \"input\" field".to_string())),
+        },
+        Ok(_) => Err(Error::BadRequest("Expected a JSON string or object".to_string())),
+        Err(e) => Err(Error::BadRequest(format!("Invalid JSON: {}", e))),
+    };
+
+    let record = match input.and_then(|input| process_data(&input)) {
+        Ok(result) => serde_json::json!({"line": line_number, "result": result, "status": "success"}),
+        Err(e) => error_record(line_number, &e),
+    };
+    Some(record)
+}
+
+/// Error object for a failed NDJSON line.
+fn error_record(line_number: usize, error: &Error) -> serde_json::Value {
+    serde_json::json!({
+        "line": line_number,
+        "error": error.to_string(),
+        "code": error.code(),
+        "status": "error",
+    })
+}
+
+async fn metrics(state: Arc<AppState>, request: Request) -> Response {
+    if let Some(token) = &state.config.security.metrics_auth_token {
+        if !is_authorized(&request, token) {
+            return Response::json(401, r#"{"error":"Unauthorized","status":"error"}"#)
+                .with_header("WWW-Authenticate", r#"Bearer realm="metrics""#);
+        }
+    }
+
+    state.metrics.set_gauge("uptime_seconds", state.uptime.elapsed_secs() as f64);
+
+    let format = state.config.observability.metrics_format;
+    let body = match format {
+        // Rendered straight into the response body
+        MetricsFormat::Json => {
+            let mut body = Vec::new();
+            if let Err(e) = state.metrics.write_metrics_json(&mut body) {
+                return error_response(&e);
+            }
+            body
+        }
+        _ => state.metrics.export_with(format.exporter().as_ref()).into_bytes(),
+    };
+    Response::bytes(200, format.content_type(), body)
+}
+
+/// Check a request's `Authorization` header against the expected token.
+///
+/// Accepts `Bearer <token>` or `Basic` credentials with a username and the
+/// token as the password.
+fn is_authorized(request: &Request, token: &str) -> bool {
+    let Some(Ok((scheme, credentials))) = request.header("Authorization").map(parse_authorization) else {
+        return false;
+    };
+
+    let presented = if scheme.eq_ignore_ascii_case("Bearer") {
+        credentials
+    } else if scheme.eq_ignore_ascii_case("Basic") {
+        match decode_basic_auth(&credentials) {
+            Ok((_, password)) => password,
+            Err(_) => return false,
+        }
+    } else {
+        return false;
+    };
+
+    constant_time_eq(presented.as_bytes(), token.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::TrailingSlash;
+    use std::net::SocketAddr;
+
+    use tokio::io::AsyncReadExt;
+
+    /// Start a server on an ephemeral port and return its address.
+    async fn spawn_server(server: Server) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(server.serve(listener));
+        addr
+    }
+
+    /// Read a single response, using `Content-Length` to find its end.
+    async fn read_response<S: AsyncRead + Unpin>(stream: &mut S) -> String {
+        let mut response = Vec::new();
+        let mut byte = [0u8; 1];
+
+        while !response.ends_with(b"\r\n\r\n") {
+            if stream.read(&mut byte).await.unwrap() == 0 {
+                return String::from_utf8_lossy(&response).into_owned();
+            }
+            response.push(byte[0]);
+        }
+
+        let head = String::from_utf8_lossy(&response).into_owned();
+        let length = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .map_or(0, |value| value.trim().parse().unwrap());
+
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await.unwrap();
+        response.extend_from_slice(&body);
+
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    /// Read one chunk of a chunked body, empty for the last.
+    async fn read_chunk<S: AsyncRead + Unpin>(stream: &mut S) -> String {
+        let mut size_line = Vec::new();
+        while !size_line.ends_with(b"\r\n") {
+            size_line.push(stream.read_u8().await.unwrap());
+        }
+        let size = usize::from_str_radix(std::str::from_utf8(&size_line).unwrap().trim(), 16).unwrap();
+
+        let mut chunk = vec![0; size + 2];
+        stream.read_exact(&mut chunk).await.unwrap();
+        assert!(chunk.ends_with(b"\r\n"));
+        chunk.truncate(size);
+        String::from_utf8(chunk).unwrap()
+    }
+
+    /// Send a raw request on a new connection and read the response.
+    async fn send_raw(addr: SocketAddr, request: &[u8]) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request).await.unwrap();
+        read_response(&mut stream).await
+    }
+
+    #[tokio::test]
+    async fn test_default_routes() {
+        let addr = spawn_server(Server::new(Config::default())).await;
+
+        let response = send_raw(addr, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        let response = send_raw(addr, b"GET /missing HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+    }
+
+    #[tokio::test]
+    async fn test_expect_continue() {
+        let addr = spawn_server(Server::new(Config::default())).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        stream
+            .write_all(b"POST /process HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n")
+            .await
+            .unwrap();
+        let interim = read_response(&mut stream).await;
+        assert_eq!(interim, "HTTP/1.1 100 Continue\r\n\r\n");
+
+        stream.write_all(b"hello").await.unwrap();
+        let response = read_response(&mut stream).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Processed: HELLO"));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_request_line_returns_414() {
+        let mut config = Config::default();
+        config.server.max_request_line_bytes = 64;
+        let addr = spawn_server(Server::new(config)).await;
+
+        let request = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(1024));
+        let response = send_raw(addr, request.as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 414 URI Too Long"));
+        assert!(response.contains("URI_TOO_LONG"));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_header_value_returns_431() {
+        let addr = spawn_server(Server::new(Config::default())).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let (mut reader, mut writer) = stream.split();
+
+        // The server answers without reading the rest, so the write may fail
+        let request = format!("GET /health HTTP/1.1\r\nX-Huge: {}\r\n\r\n", "a".repeat(4 << 20));
+        let send = async {
+            let _ = writer.write_all(request.as_bytes()).await;
+        };
+        let receive = async {
+            let mut response = Vec::new();
+            let _ = reader.read_to_end(&mut response).await;
+            String::from_utf8_lossy(&response).into_owned()
+        };
+        let ((), response) = tokio::join!(send, receive);
+
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large"), "{}", response);
+        assert!(response.contains("HEADER_TOO_LARGE"));
+    }
+
+    #[tokio::test]
+    async fn test_binary_body_reaches_handler_unmodified() {
+        let server = Server::new(Config::default()).with_handler(|request: Request| async move {
+            Response::new(200, "text/plain", crate::utils::base64_encode(request.body_bytes()))
+        });
+        let addr = spawn_server(server).await;
+
+        let payload: Vec<u8> = (0..=255).collect();
+        let mut request = format!("POST /upload HTTP/1.1\r\nContent-Length: {}\r\n\r\n", payload.len()).into_bytes();
+        request.extend_from_slice(&payload);
+
+        let response = send_raw(addr, &request).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        let echoed = crate::utils::base64_decode(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(echoed, payload);
+
+        // The default /process route still needs text
+        let addr = spawn_server(Server::new(Config::default())).await;
+        let response = send_raw(addr, b"POST /process HTTP/1.1\r\nContent-Length: 2\r\n\r\n\xff\xfe").await;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+        assert!(response.contains("not valid UTF-8"));
+        assert!(response.contains(r#""code":"BAD_REQUEST""#));
+    }
+
+    #[tokio::test]
+    async fn test_slow_client_is_reported() {
+        let (logs, _guard) = crate::testing::LogCapture::install();
+        let mut config = Config::default();
+        config.server.slow_client_threshold = Duration::from_millis(100);
+        let metrics = MetricsCollector::new();
+        let addr = spawn_server(Server::new(config).with_metrics(metrics.clone())).await;
+
+        let response = send_raw(addr, b"GET /health HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(metrics.get_counter("slow_clients_total"), 0);
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        stream.write_all(b"GET /health HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+        let response = read_response(&mut stream).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        assert_eq!(metrics.get_counter("slow_clients_total"), 1);
+        let histogram = metrics.get_histogram("time_to_first_request_seconds").unwrap();
+        assert_eq!(histogram.count, 2);
+        assert!(histogram.sum >= 0.25);
+        assert!(logs.contents().contains("Slow client took"));
+    }
+
+    #[tokio::test]
+    async fn test_head_request_omits_body() {
+        let addr = spawn_server(Server::new(Config::default())).await;
+
+        let get = send_raw(addr, b"GET / HTTP/1.1\r\n\r\n").await;
+        let (get_head, get_body) = get.split_once("\r\n\r\n").unwrap();
+        assert!(!get_body.is_empty());
+
+        // A HEAD response's Content-Length describes the GET body, so read until close
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"HEAD / HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut head = String::new();
+        stream.read_to_string(&mut head).await.unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", get_body.len())));
+        assert!(head.ends_with("\r\n\r\n"));
+        assert!(get_head.contains("Content-Type: text/html"));
+        assert!(head.contains("Content-Type: text/html"));
+    }
+
+    #[tokio::test]
+    async fn test_method_is_case_insensitive() {
+        let addr = spawn_server(Server::new(Config::default())).await;
+
+        let response = send_raw(addr, b"get /health HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        let response = send_raw(addr, b"BREW /health HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 501 Not Implemented"));
+        assert!(response.contains("NOT_IMPLEMENTED"));
+    }
+
+    #[tokio::test]
+    async fn test_panicking_handler_returns_500() {
+        let server = Server::new(Config::default()).with_handler(|_request| async {
+            panic!("handler exploded");
+        });
+        let addr = spawn_server(server).await;
+
+        let response = send_raw(addr, b"GET / HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error"));
+
+        // The server keeps accepting connections after a panic
+        let response = send_raw(addr, b"GET / HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error"));
+    }
+
+    #[tokio::test]
+    async fn test_panic_is_logged_with_request_context() {
+        let (logs, _guard) = crate::testing::LogCapture::install();
+        let server = Server::new(Config::default()).with_handler(|_request| async {
+            panic!("handler exploded");
+        });
+        let addr = spawn_server(server).await;
+
+        let response = send_raw(addr, b"GET /orders/7 HTTP/1.1\r\nX-Request-Id: req-42\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error"));
+        assert!(response.contains("X-Request-Id: req-42\r\n"));
+
+        let logs = logs.contents();
+        let line = logs.lines().find(|line| line.contains("Handler panicked")).expect("panic log");
+        assert!(line.contains("ERROR"), "{}", line);
+        assert!(line.contains("handler exploded"), "{}", line);
+        assert!(line.contains("request_id=req-42"), "{}", line);
+        assert!(line.contains("method=GET"), "{}", line);
+        assert!(line.contains("path=/orders/7"), "{}", line);
+        assert!(line.contains("location=\"src/server.rs:"), "{}", line);
+    }
+
+    #[test]
+    fn test_release_profile_unwinds() {
+        // Tests always build with unwinding, so check the profile release binaries use
+        let manifest = include_str!("../Cargo.toml");
+        let release = manifest
+            .split("\n[")
+            .find(|section| section.starts_with("profile.release]"))
+            .expect("release profile");
+        let panic = release
+            .lines()
+            .find_map(|line| line.split_once('=').filter(|(key, _)| key.trim() == "panic"))
+            .map(|(_, value)| value.trim().trim_matches('"'));
+        assert_eq!(panic, Some("unwind"), "catch_unwind in call_handler needs panic = \"unwind\"");
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_serves_multiple_requests() {
+        let addr = spawn_server(Server::new(Config::default())).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        for _ in 0..3 {
+            stream.write_all(b"GET /health HTTP/1.1\r\n\r\n").await.unwrap();
+            let response = read_response(&mut stream).await;
+            assert!(response.starts_with("HTTP/1.1 200 OK"));
+            assert!(response.contains("Connection: keep-alive"));
+        }
+
+        stream.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+        let response = read_response(&mut stream).await;
+        assert!(response.contains("Connection: close"));
+
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_http10_connection_handling() {
+        let addr = spawn_server(Server::new(Config::default())).await;
+
+        // HTTP/1.0 closes after one response unless asked otherwise, and needs no Host
+        let response = send_raw(addr, b"GET /health HTTP/1.0\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.0 200 OK"), "{}", response);
+        assert!(response.contains("Connection: close"));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        for _ in 0..2 {
+            stream.write_all(b"GET /health HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n").await.unwrap();
+            let response = read_response(&mut stream).await;
+            assert!(response.starts_with("HTTP/1.0 200 OK"));
+            assert!(response.contains("Connection: keep-alive"));
+        }
+
+        stream.write_all(b"GET /health HTTP/1.0\r\n\r\n").await.unwrap();
+        let response = read_response(&mut stream).await;
+        assert!(response.contains("Connection: close"));
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_idle_keep_alive_connection_is_closed() {
+        let mut config = Config::default();
+        config.server.keep_alive_timeout = Duration::from_millis(100);
+        config.server.idle_connection_timeout = Duration::from_millis(100);
+        let addr = spawn_server(Server::new(config)).await;
+
+        // A connection that never sends a request is closed
+        let mut idle = TcpStream::connect(addr).await.unwrap();
+        let mut buffer = [0u8; 16];
+        let read = timeout(Duration::from_secs(2), idle.read(&mut buffer)).await;
+        assert_eq!(read.unwrap().unwrap(), 0);
+
+        // A kept-alive connection is closed once it goes quiet
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let response = read_response(&mut stream).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        let read = timeout(Duration::from_secs(2), stream.read(&mut buffer)).await;
+        assert_eq!(read.unwrap().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_auth_token() {
+        let mut config = Config::default();
+        config.security.metrics_auth_token = Some("scrape-token".to_string());
+        let addr = spawn_server(Server::new(config)).await;
+
+        let response = send_raw(addr, b"GET /metrics HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized"));
+        assert!(response.contains("WWW-Authenticate: Bearer"));
+
+        let response = send_raw(addr, b"GET /metrics HTTP/1.1\r\nAuthorization: Bearer wrong-token\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized"));
+
+        let response = send_raw(addr, b"GET /metrics HTTP/1.1\r\nAuthorization: Bearer scrape-token\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        let basic = format!(
+            "GET /metrics HTTP/1.1\r\nAuthorization: Basic {}\r\n\r\n",
+            crate::utils::base64_encode("prometheus:scrape-token")
+        );
+        let response = send_raw(addr, basic.as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        // Basic credentials need a username
+        let basic = format!(
+            "GET /metrics HTTP/1.1\r\nAuthorization: Basic {}\r\n\r\n",
+            crate::utils::base64_encode(":scrape-token")
+        );
+        let response = send_raw(addr, basic.as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized"));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_open_without_token() {
+        let metrics = MetricsCollector::new();
+        let addr = spawn_server(Server::new(Config::default()).with_metrics(metrics.clone())).await;
+
+        let response = send_raw(addr, b"GET /metrics HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        // Uptime is measured from server construction, not the Unix epoch
+        let body: serde_json::Value = serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert!(body["gauges"]["uptime_seconds"].as_f64().unwrap() < 60.0);
+        assert_eq!(metrics.get_gauge("uptime_seconds"), body["gauges"]["uptime_seconds"].as_f64());
+    }
+
+    #[tokio::test]
+    async fn test_metrics_observability_config() {
+        let mut config = Config::default();
+        config.observability.metrics_path = "/internal/metrics".to_string();
+        config.observability.metrics_format = crate::metrics::MetricsFormat::Prometheus;
+        let addr = spawn_server(Server::new(config)).await;
+
+        let response = send_raw(addr, b"GET /metrics HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+
+        let response = send_raw(addr, b"GET /internal/metrics HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+        assert!(response.contains("# TYPE uptime_seconds gauge"));
+
+        let mut config = Config::default();
+        config.observability.metrics_enabled = false;
+        let addr = spawn_server(Server::new(config)).await;
+        let response = send_raw(addr, b"GET /metrics HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+
+    #[tokio::test]
+    async fn test_custom_middleware_wraps_routes() {
+        struct Tag;
+
+        #[async_trait::async_trait]
+        impl Middleware for Tag {
+            async fn handle(&self, request: Request, next: crate::middleware::Next) -> Response {
+                next.run(request).await.with_header("X-Tag", "tagged")
+            }
+        }
+
+        let addr = spawn_server(Server::new(Config::default()).with_middleware(Tag)).await;
+        let response = send_raw(addr, b"GET /health HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("X-Tag: tagged"));
+    }
+
+    #[tokio::test]
+    async fn test_authenticator_protects_process() {
+        let authenticator = crate::auth::ApiKeyAuthenticator::new(vec!["secret-key".to_string()]);
+        let server = Server::new(Config::default()).with_authenticator(Arc::new(authenticator));
+        let addr = spawn_server(server).await;
+
+        let response = send_raw(addr, b"POST /process HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello").await;
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized"));
+
+        let response = send_raw(
+            addr,
+            b"POST /process HTTP/1.1\r\nX-API-Key: secret-key\r\nContent-Length: 5\r\n\r\nhello",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Processed: HELLO"));
+    }
+
+    fn response_traceparent(response: &str) -> TraceContext {
+        let value = response
+            .lines()
+            .find_map(|line| line.strip_prefix("traceparent: "))
+            .expect("response carries a traceparent header");
+        TraceContext::parse(value).expect("traceparent is well formed")
+    }
+
+    #[tokio::test]
+    async fn test_traceparent_propagation() {
+        let addr = spawn_server(Server::new(Config::default())).await;
+
+        let response = send_raw(
+            addr,
+            b"GET / HTTP/1.1\r\ntraceparent: 00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01\r\n\r\n",
+        )
+        .await;
+        let context = response_traceparent(&response);
+        assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_ne!(context.span_id, "00f067aa0ba902b7");
+
+        // Malformed values are replaced with a fresh trace rather than rejected
+        let response = send_raw(addr, b"GET / HTTP/1.1\r\ntraceparent: 00-bogus-01\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        let context = response_traceparent(&response);
+        assert_ne!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+
+        // Absent values start a new trace
+        let response = send_raw(addr, b"GET / HTTP/1.1\r\n\r\n").await;
+        response_traceparent(&response);
+    }
+
+    #[tokio::test]
+    async fn test_slow_handler_times_out_with_504() {
+        let mut config = Config::default();
+        config.server.request_timeout = Duration::from_secs(1);
+        config.server.keep_alive_timeout = Duration::from_millis(500);
+        let server = Server::new(config).with_handler(|_request| async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Response::json(200, "{}")
+        });
+        let addr = spawn_server(server).await;
+
+        let response = send_raw(addr, b"GET / HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 504 Gateway Timeout"));
+        assert!(response.contains(r#""code":"TIMEOUT""#));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_active_connections() {
+        let metrics = MetricsCollector::new();
+        let server = Server::new(Config::default())
+            .with_metrics(metrics.clone())
+            .with_handler(|_request| async {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                Response::json(200, "{}")
+            });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (trigger, shutdown) = tokio::sync::oneshot::channel::<()>();
+        let serving = tokio::spawn(server.serve_with_shutdown(listener, async {
+            let _ = shutdown.await;
+        }));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        while metrics.get_gauge("active_connections") != Some(1.0) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        trigger.send(()).unwrap();
+        let response = read_response(&mut stream).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Connection: close"));
+
+        timeout(Duration::from_secs(2), serving).await.unwrap().unwrap().unwrap();
+        assert_eq!(metrics.get_gauge("active_connections"), Some(0.0));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_pushes_final_metrics() {
+        let metrics = MetricsCollector::new();
+        let pushes = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let pusher = {
+            let pushes = pushes.clone();
+            metrics.spawn_pusher(Duration::from_secs(3600), crate::metrics::StatsdExporter, move |output| {
+                pushes.lock().unwrap().push(output);
+                Ok(())
+            })
+        };
+        let server = Server::new(Config::default())
+            .with_metrics(metrics.clone())
+            .with_metrics_pusher(pusher)
+            .with_handler(|_request| async { Response::json(200, "{}") });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (trigger, shutdown) = tokio::sync::oneshot::channel::<()>();
+        let serving = tokio::spawn(server.serve_with_shutdown(listener, async {
+            let _ = shutdown.await;
+        }));
+
+        let response = send_raw(addr, b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(pushes.lock().unwrap().is_empty());
+
+        trigger.send(()).unwrap();
+        timeout(Duration::from_secs(2), serving).await.unwrap().unwrap().unwrap();
+        let pushes = pushes.lock().unwrap();
+        assert_eq!(pushes.len(), 1);
+        assert!(pushes[0].contains("active_connections:0|g"), "{}", pushes[0]);
+    }
+
+    #[tokio::test]
+    async fn test_configured_metrics_push() {
+        let gateway = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = Config::default();
+        config.observability.metrics_push_url = Some(format!("http://{}/push", gateway.local_addr().unwrap()));
+        config.observability.metrics_format = crate::metrics::MetricsFormat::Statsd;
+        config.observability.metrics_push_interval = Duration::from_secs(3600);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (trigger, shutdown) = tokio::sync::oneshot::channel::<()>();
+        let serving = tokio::spawn(Server::new(config).serve_with_shutdown(listener, async {
+            let _ = shutdown.await;
+        }));
+        let response = send_raw(addr, b"GET /health HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        // The final push on shutdown goes to the configured URL
+        trigger.send(()).unwrap();
+        let (mut socket, _) = timeout(Duration::from_secs(2), gateway.accept()).await.unwrap().unwrap();
+        // Requests and responses are framed alike
+        let request = read_response(&mut socket).await;
+        socket.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").await.unwrap();
+        timeout(Duration::from_secs(2), serving).await.unwrap().unwrap().unwrap();
+
+        assert!(request.starts_with("POST /push HTTP/1.1\r\n"), "{}", request);
+        assert!(request.contains("active_connections:0|g"), "{}", request);
+
+        // With outbound blocking on, the loopback gateway is refused
+        let gateway = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = Config::default();
+        config.observability.metrics_push_url = Some(format!("http://{}/push", gateway.local_addr().unwrap()));
+        config.security.block_private_outbound = true;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let serving = tokio::spawn(Server::new(config).serve_with_shutdown(listener, async {}));
+        timeout(Duration::from_secs(2), serving).await.unwrap().unwrap().unwrap();
+        assert!(timeout(Duration::from_millis(100), gateway.accept()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_forces_stuck_connections() {
+        let (logs, _guard) = crate::testing::LogCapture::install();
+        let mut config = Config::default();
+        config.server.shutdown_grace_period = Duration::from_millis(200);
+        let server = Server::new(config).with_handler(|_request| std::future::pending());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (trigger, shutdown) = tokio::sync::oneshot::channel::<()>();
+        let serving = tokio::spawn(server.serve_with_shutdown(listener, async {
+            let _ = shutdown.await;
+        }));
+
+        let mut streams = Vec::new();
+        for _ in 0..2 {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+            streams.push(stream);
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let started = Instant::now();
+        trigger.send(()).unwrap();
+        timeout(Duration::from_secs(2), serving).await.unwrap().unwrap().unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(logs.contents().contains("forced 2 connections"), "{}", logs.contents());
+
+        // The aborted connections are closed without a response
+        for mut stream in streams {
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).await.unwrap();
+            assert!(rest.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handler_observes_shutdown() {
+        let server = Server::new(Config::default()).with_handler(|request: Request| async move {
+            let mut chunks = 0;
+            loop {
+                tokio::select! {
+                    _ = request.shutdown.recv() => break,
+                    _ = tokio::time::sleep(Duration::from_millis(10)) => chunks += 1,
+                }
+                if chunks == 1_000 {
+                    return Response::json(200, r#"{"finished":true}"#);
+                }
+            }
+            Response::json(200, r#"{"stopped":true}"#)
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (trigger, shutdown) = tokio::sync::oneshot::channel::<()>();
+        let serving = tokio::spawn(server.serve_with_shutdown(listener, async {
+            let _ = shutdown.await;
+        }));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /stream HTTP/1.1\r\n\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let started = Instant::now();
+        trigger.send(()).unwrap();
+        let response = read_response(&mut stream).await;
+        assert!(response.ends_with(r#"{"stopped":true}"#));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        timeout(Duration::from_secs(2), serving).await.unwrap().unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tls_handshake() {
+        let (cert, key) = crate::utils::generate_self_signed_cert(&["localhost"]).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let (cert_path, key_path) = (dir.path().join("cert.pem"), dir.path().join("key.pem"));
+        std::fs::write(&cert_path, &cert).unwrap();
+        std::fs::write(&key_path, &key).unwrap();
+
+        let mut config = Config::default();
+        config.server.tls_enabled = true;
+        config.server.tls_cert_path = Some(cert_path);
+        config.server.tls_key_path = Some(key_path);
+        let addr = spawn_server(Server::new(config)).await;
+
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut &cert[..]).unwrap() {
+            roots.add(&rustls::Certificate(cert)).unwrap();
+        }
+        let client = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client));
+
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let server_name = rustls::ServerName::try_from("localhost").unwrap();
+        let mut stream = connector.connect(server_name, socket).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        assert!(read_response(&mut stream).await.starts_with("HTTP/1.1 200 OK"));
+
+        // The connection is kept alive for further requests
+        stream.write_all(b"GET /missing HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+        assert!(read_response(&mut stream).await.starts_with("HTTP/1.1 404 Not Found"));
+
+        // Plain HTTP is not answered on a TLS listener
+        let response = send_raw(addr, b"GET / HTTP/1.1\r\n\r\n").await;
+        assert!(!response.contains("200 OK"));
+    }
+
+    #[tokio::test]
+    async fn test_tls_minimum_version() {
+        let (cert, key) = crate::utils::generate_self_signed_cert(&["localhost"]).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let (cert_path, key_path) = (dir.path().join("cert.pem"), dir.path().join("key.pem"));
+        std::fs::write(&cert_path, &cert).unwrap();
+        std::fs::write(&key_path, &key).unwrap();
+
+        let mut config = Config::default();
+        config.server.tls_enabled = true;
+        config.server.tls_cert_path = Some(cert_path);
+        config.server.tls_key_path = Some(key_path);
+        config.server.min_tls_version = "1.3".to_string();
+        config.server.cipher_policy = crate::tls::CipherPolicy::Modern;
+        let addr = spawn_server(Server::new(config)).await;
+
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut &cert[..]).unwrap() {
+            roots.add(&rustls::Certificate(cert)).unwrap();
+        }
+        let connect = |version: &'static rustls::SupportedProtocolVersion| {
+            let client = rustls::ClientConfig::builder()
+                .with_safe_default_cipher_suites()
+                .with_safe_default_kx_groups()
+                .with_protocol_versions(&[version])
+                .unwrap()
+                .with_root_certificates(roots.clone())
+                .with_no_client_auth();
+            let connector = tokio_rustls::TlsConnector::from(Arc::new(client));
+            async move {
+                let socket = TcpStream::connect(addr).await.unwrap();
+                let server_name = rustls::ServerName::try_from("localhost").unwrap();
+                connector.connect(server_name, socket).await
+            }
+        };
+
+        assert!(connect(&rustls::version::TLS12).await.is_err());
+
+        let mut stream = connect(&rustls::version::TLS13).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+        assert!(read_response(&mut stream).await.starts_with("HTTP/1.1 200 OK"));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_protocol() {
+        let mut config = Config::default();
+        config.server.proxy_protocol = true;
+        let server = Server::new(config).with_handler(|request: Request| async move {
+            Response::json(200, request.peer.map(|peer| peer.to_string()).unwrap_or_default())
+        });
+        let addr = spawn_server(server).await;
+
+        let response = send_raw(addr, b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 443\r\nGET / HTTP/1.1\r\n\r\n").await;
+        assert!(response.ends_with("203.0.113.7:51234"), "{}", response);
+
+        let response = send_raw(addr, b"PROXY TCP6 2001:db8::7 2001:db8::1 40000 443\r\nGET / HTTP/1.1\r\n\r\n").await;
+        assert!(response.ends_with("[2001:db8::7]:40000"), "{}", response);
+
+        // A malformed, unknown or missing header falls back to the socket peer
+        for request in [
+            &b"PROXY TCP4 not-an-ip 10.0.0.1 51234 443\r\nGET / HTTP/1.1\r\n\r\n"[..],
+            b"PROXY UNKNOWN\r\nGET / HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/1.1\r\n\r\n",
+        ] {
+            let response = send_raw(addr, request).await;
+            assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+            assert!(response.contains("\r\n\r\n127.0.0.1:"), "{}", response);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_proxy_header() {
+        // The header is consumed and the request left for the HTTP parser
+        let mut reader = BufReader::new(&b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 443\r\nGET / HTTP/1.1\r\n\r\n"[..]);
+        let client = read_proxy_header(&mut reader).await.unwrap();
+        assert_eq!(client, Some("203.0.113.7:51234".parse().unwrap()));
+        assert_eq!(reader.fill_buf().await.unwrap(), b"GET / HTTP/1.1\r\n\r\n");
+
+        let mut reader = BufReader::new(&b"GET / HTTP/1.1\r\n\r\n"[..]);
+        assert_eq!(read_proxy_header(&mut reader).await.unwrap(), None);
+        assert_eq!(reader.fill_buf().await.unwrap(), b"GET / HTTP/1.1\r\n\r\n");
+
+        let long = format!("PROXY {}\r\n", "x".repeat(MAX_PROXY_HEADER_BYTES));
+        assert!(read_proxy_header(&mut BufReader::new(long.as_bytes())).await.is_err());
+        assert!(read_proxy_header(&mut BufReader::new(&b"PROXY TCP4 203.0.113.7"[..])).await.is_err());
+    }
+
+    #[test]
+    fn test_parse_proxy_header() {
+        let parsed = parse_proxy_header("PROXY TCP4 192.0.2.1 198.51.100.1 56324 443").unwrap();
+        assert_eq!(parsed, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(parse_proxy_header("PROXY UNKNOWN ffff::1 ffff::2 1 2").unwrap(), None);
+
+        for malformed in [
+            "PROXY TCP4 192.0.2.1 198.51.100.1 56324",
+            "PROXY TCP6 192.0.2.1 198.51.100.1 56324 443",
+            "PROXY TCP4 192.0.2.1 198.51.100.1 99999 443",
+            "PROXY UDP4 192.0.2.1 198.51.100.1 56324 443",
+            "PROXY",
+        ] {
+            assert!(matches!(parse_proxy_header(malformed), Err(Error::BadRequest(_))), "{}", malformed);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_requests_are_bounded() {
+        let mut config = Config::default();
+        config.server.max_pipelined_requests = 3;
+        let addr = spawn_server(Server::new(config)).await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut requests = Vec::new();
+        for i in 0..6 {
+            requests.extend_from_slice(format!("GET /?n={} HTTP/1.1\r\n\r\n", i).as_bytes());
+        }
+        stream.write_all(&requests).await.unwrap();
+
+        let mut responses = String::new();
+        timeout(Duration::from_secs(2), stream.read_to_string(&mut responses)).await.unwrap().unwrap();
+        assert_eq!(responses.matches("HTTP/1.1 200 OK").count(), 3);
+        assert_eq!(responses.matches("Connection: close").count(), 1);
+
+        // Requests sent one at a time are not pipelined
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        for _ in 0..5 {
+            stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+            assert!(read_response(&mut stream).await.contains("Connection: keep-alive"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_stream() {
+        let addr = spawn_server(Server::new(Config::default())).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                b"POST /process-stream HTTP/1.1\r\nContent-Type: application/x-ndjson\r\n\
+                  Transfer-Encoding: chunked\r\n\r\n8\r\n\"hello\"\n\r\n",
+            )
+            .await
+            .unwrap();
+
+        // The first result arrives while the rest of the body is still unsent
+        let head = read_response(&mut stream).await;
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(head.contains("Content-Type: application/x-ndjson"));
+        let first: serde_json::Value = serde_json::from_str(&read_chunk(&mut stream).await).unwrap();
+        assert_eq!(first, serde_json::json!({"line": 1, "result": "Processed: HELLO", "status": "success"}));
+
+        // A line split across chunks, a malformed line, a blank line and two unprocessable inputs
+        let rest = "{\"input\": \"world\"}\nnot json\n\n[1]\n\"\"";
+        let (head, tail) = rest.split_at(12);
+        let chunks = format!("{:x}\r\n{}\r\n{:x}\r\n{}\r\n0\r\n\r\n", head.len(), head, tail.len(), tail);
+        stream.write_all(chunks.as_bytes()).await.unwrap();
+
+        let mut records = Vec::new();
+        loop {
+            let chunk = read_chunk(&mut stream).await;
+            if chunk.is_empty() {
+                break;
+            }
+            for line in chunk.lines() {
+                records.push(serde_json::from_str::<serde_json::Value>(line).unwrap());
+            }
+        }
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0]["result"], "Processed: WORLD");
+        assert_eq!(records[0]["line"], 2);
+        assert_eq!(records[1]["status"], "error");
+        assert_eq!(records[1]["code"], "BAD_REQUEST");
+        assert_eq!(records[1]["line"], 3);
+        assert_eq!(records[2]["line"], 5);
+        assert_eq!(records[2]["status"], "error");
+        assert_eq!(records[3]["line"], 6);
+        assert_eq!(records[3]["code"], "INVALID_INPUT");
+
+        // The connection stays usable, including for Content-Length bodies
+        stream
+            .write_all(
+                b"POST /process-stream HTTP/1.1\r\nContent-Type: application/x-ndjson\r\n\
+                  Content-Length: 5\r\n\r\n\"abc\"",
+            )
+            .await
+            .unwrap();
+        assert!(read_response(&mut stream).await.starts_with("HTTP/1.1 200 OK"));
+        assert!(read_chunk(&mut stream).await.contains("Processed: ABC"));
+        assert!(read_chunk(&mut stream).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cors_allowed_origin() {
+        let mut config = Config::default();
+        config.security.cors_origins = vec!["https://app.example.com".to_string()];
+        let addr = spawn_server(Server::new(config)).await;
+
+        let response = send_raw(addr, b"GET / HTTP/1.1\r\nOrigin: https://APP.example.com\r\n\r\n").await;
+        assert!(response.contains("Access-Control-Allow-Origin: https://APP.example.com"));
+
+        let response = send_raw(addr, b"GET / HTTP/1.1\r\nOrigin: https://evil.example.com\r\n\r\n").await;
+        assert!(!response.contains("Access-Control-Allow-Origin"));
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight() {
+        let mut config = Config::default();
+        config.security.cors_origins = vec!["https://app.example.com".to_string()];
+        config.security.cors_methods = vec!["GET".to_string(), "PUT".to_string()];
+        config.security.cors_allowed_headers = vec!["Content-Type".to_string(), "X-Api-Key".to_string()];
+        let addr = spawn_server(Server::new(config)).await;
+
+        let response = send_raw(
+            addr,
+            b"OPTIONS /items HTTP/1.1\r\nOrigin: https://app.example.com\r\n\
+              Access-Control-Request-Method: PUT\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 204 No Content"));
+        assert!(response.contains("Access-Control-Allow-Origin: https://app.example.com\r\n"));
+        assert!(response.contains("Access-Control-Allow-Methods: GET, PUT\r\n"));
+        assert!(response.contains("Access-Control-Allow-Headers: Content-Type, X-Api-Key\r\n"));
+
+        // Disallowed origins and plain OPTIONS requests go to the handler
+        let response = send_raw(
+            addr,
+            b"OPTIONS /items HTTP/1.1\r\nOrigin: https://evil.example.com\r\n\
+              Access-Control-Request-Method: PUT\r\n\r\n",
+        )
+        .await;
+        assert!(!response.contains("Access-Control-Allow-Methods"));
+
+        let response = send_raw(addr, b"OPTIONS /items HTTP/1.1\r\nOrigin: https://app.example.com\r\n\r\n").await;
+        assert!(!response.contains("Access-Control-Allow-Methods"));
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash_policy() {
+        let addr = spawn_server(Server::new(Config::default())).await;
+        let response = send_raw(addr, b"GET /health/ HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 308 Permanent Redirect"));
+        assert!(response.contains("Location: /health\r\n"));
+
+        let mut config = Config::default();
+        config.server.trailing_slash = TrailingSlash::Merge;
+        let addr = spawn_server(Server::new(config)).await;
+        let response = send_raw(addr, b"GET /health/ HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        let mut config = Config::default();
+        config.server.trailing_slash = TrailingSlash::Strict;
+        let addr = spawn_server(Server::new(config)).await;
+        let response = send_raw(addr, b"GET /health/ HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+    }
+
+    #[tokio::test]
+    async fn test_default_charset_flag() {
+        let server = Server::new(Config::default()).with_handler(|request: Request| async move {
+            match request.path.as_str() {
+                "/bytes" => Response::new(200, "application/octet-stream", "raw"),
+                _ => Response::json(200, "{}"),
+            }
+        });
+        let addr = spawn_server(server).await;
+
+        let response = send_raw(addr, b"GET /json HTTP/1.1\r\n\r\n").await;
+        assert!(response.contains("Content-Type: application/json; charset=utf-8\r\n"));
+        let response = send_raw(addr, b"GET /bytes HTTP/1.1\r\n\r\n").await;
+        assert!(response.contains("Content-Type: application/octet-stream\r\n"));
+
+        let mut config = Config::default();
+        config.server.default_charset = false;
+        let addr = spawn_server(Server::new(config)).await;
+        let response = send_raw(addr, b"GET /health HTTP/1.1\r\n\r\n").await;
+        assert!(response.contains("Content-Type: application/json\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_custom_root_and_json_not_found() {
+        let server = Server::new(Config::default())
+            .with_root(|_request| async { Response::json(200, r#"{"service":"orders"}"#) });
+        let addr = spawn_server(server).await;
+
+        let response = send_raw(addr, b"GET / HTTP/1.1\r\n\r\n").await;
+        assert!(response.ends_with(r#"{"service":"orders"}"#));
+
+        let response = send_raw(addr, b"GET /missing HTTP/1.1\r\nAccept: application/json\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+        assert!(response.contains(r#""code":"NOT_FOUND""#));
+
+        let server = Server::new(Config::default())
+            .with_not_found(|request: Request| async move { Response::html(404, format!("<p>No {}</p>", request.path)) });
+        let addr = spawn_server(server).await;
+        let response = send_raw(addr, b"GET /missing HTTP/1.1\r\n\r\n").await;
+        assert!(response.ends_with("<p>No /missing</p>"));
+    }
+
+    #[tokio::test]
+    async fn test_health_reports_failing_checks() {
+        let mut checker = HealthChecker::new();
+        checker.add_check(|| Err(Error::Database("unreachable".to_string())));
+        let addr = spawn_server(Server::new(Config::default()).with_health_checker(checker)).await;
+
+        let response = send_raw(addr, b"GET /health HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"));
+        assert!(response.contains(r#""status":"unhealthy""#));
+    }
+
+    #[tokio::test]
+    async fn test_custom_request_id_header() {
+        let mut config = Config::default();
+        config.server.request_id_header = "X-Correlation-Id".to_string();
+        let server = Server::new(config).with_handler(|request: Request| async move {
+            let id = request.header("X-Correlation-Id").unwrap_or_default().to_string();
+            Response::json(200, format!(r#"{{"seen":"{}"}}"#, id))
+        });
+        let addr = spawn_server(server).await;
+
+        // Ingress: an incoming ID reaches the handler and is echoed back
+        let response = send_raw(addr, b"GET / HTTP/1.1\r\nX-Correlation-Id: abc-123\r\n\r\n").await;
+        assert!(response.contains("X-Correlation-Id: abc-123\r\n"));
+        assert!(response.ends_with(r#"{"seen":"abc-123"}"#));
+        assert!(!response.contains("X-Request-Id"));
+
+        // Egress: a missing ID is generated under the configured name
+        let response = send_raw(addr, b"GET / HTTP/1.1\r\n\r\n").await;
+        let id = response
+            .lines()
+            .find_map(|line| line.strip_prefix("X-Correlation-Id: "))
+            .expect("request ID header");
+        assert_eq!(id.len(), 16);
+        assert!(response.ends_with(&format!(r#"{{"seen":"{}"}}"#, id)));
+    }
+
+    #[tokio::test]
+    async fn test_health_content_negotiation() {
+        let mut checker = HealthChecker::new();
+        checker.add_named_check("disk", &[], || Ok(()));
+        checker.add_named_check("database", &[], || Err(Error::Database("unreachable".to_string())));
+        checker.add_named_check("cache", &["database"], || Ok(()));
+        checker.add_optional_check("search", &["disk"], 1.0, || Ok(()));
+        let addr = spawn_server(Server::new(Config::default()).with_health_checker(checker)).await;
+
+        let response = send_raw(addr, b"GET /health HTTP/1.1\r\nAccept: text/plain\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"));
+        assert!(response.contains("Content-Type: text/plain"));
+        assert!(response.ends_with("\r\n\r\nUNHEALTHY"));
+
+        for accept in ["application/json", "text/plain;q=0.5, application/json"] {
+            let request = format!("GET /health HTTP/1.1\r\nAccept: {}\r\n\r\n", accept);
+            let response = send_raw(addr, request.as_bytes()).await;
+            assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"), "{}", accept);
+            let body: serde_json::Value = serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+            assert_eq!(body["status"], "unhealthy");
+            assert_eq!(body["score"], 0.0);
+            assert_eq!(body["checks"][0]["name"], "disk");
+            assert_eq!(body["checks"][1]["status"], "failed");
+            assert_eq!(body["checks"][2]["status"], "skipped");
+        }
+
+        let addr = spawn_server(Server::new(Config::default())).await;
+        let response = send_raw(addr, b"GET /health HTTP/1.1\r\nAccept: text/plain\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("\r\n\r\nOK"));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_content_length() {
+        let addr = spawn_server(Server::new(Config::default())).await;
+        for length in ["Content-Length: -5", "Content-Length: abc", "Content-Length: 5\r\nContent-Length: 6"] {
+            let request = format!("POST /process HTTP/1.1\r\n{}\r\n\r\nhello", length);
+            let response = send_raw(addr, request.as_bytes()).await;
+            assert!(response.starts_with("HTTP/1.1 400 Bad Request"), "{}: {}", length, response);
+        }
+
+        // Streamed bodies are checked the same way
+        let request = b"POST /process-stream HTTP/1.1\r\nContent-Type: application/x-ndjson\r\n\
+                        Content-Length: 5, 7\r\n\r\nhello";
+        assert!(send_raw(addr, request).await.starts_with("HTTP/1.1 400 Bad Request"));
+
+        // Agreeing duplicates and an empty body are fine
+        let response = send_raw(addr, b"POST /process HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\nhello").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        let response = send_raw(addr, b"GET /health HTTP/1.1\r\nContent-Length: 0\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_body_is_rejected() {
+        let mut config = Config::default();
+        config.server.max_body_bytes = 16;
+        let addr = spawn_server(Server::new(config)).await;
+
+        let response = send_raw(addr, b"POST /process HTTP/1.1\r\nContent-Length: 1000000000000\r\n\r\nhello").await;
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large"), "{}", response);
+        assert!(response.contains(r#""code":"PAYLOAD_TOO_LARGE""#));
+
+        let response = send_raw(
+            addr,
+            b"POST /process HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n10\r\n0123456789abcdef\r\n1\r\nx\r\n0\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large"), "{}", response);
+
+        let response = send_raw(addr, b"POST /process HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        // Rejected before the client is told to send the body
+        let response = send_raw(
+            addr,
+            b"POST /process HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 17\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_process_stream_line_limit() {
+        let mut config = Config::default();
+        config.server.max_body_bytes = 16;
+        let addr = spawn_server(Server::new(config)).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        // A line that never ends is cut off instead of buffered
+        let body = format!("\"ok\"\n\"{}", "x".repeat(64));
+        let request = format!(
+            "POST /process-stream HTTP/1.1\r\nContent-Type: application/x-ndjson\r\n\
+             Transfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n",
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        assert!(read_response(&mut stream).await.starts_with("HTTP/1.1 200 OK"));
+
+        let mut records = Vec::new();
+        loop {
+            let chunk = read_chunk(&mut stream).await;
+            if chunk.is_empty() {
+                break;
+            }
+            for line in chunk.lines() {
+                records.push(serde_json::from_str::<serde_json::Value>(line).unwrap());
+            }
+        }
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["result"], "Processed: OK");
+        assert_eq!(records[1]["line"], 2);
+        assert_eq!(records[1]["code"], "PAYLOAD_TOO_LARGE");
+
+        // The unread rest of the body is discarded and the connection stays usable
+        stream.write_all(b"0\r\n\r\nGET /health HTTP/1.1\r\n\r\n").await.unwrap();
+        assert!(read_response(&mut stream).await.starts_with("HTTP/1.1 200 OK"));
+    }
+
+    #[tokio::test]
+    async fn test_too_many_headers() {
+        let mut config = Config::default();
+        config.server.max_header_count = 10;
+        let addr = spawn_server(Server::new(config)).await;
+
+        let headers: String = (0..11).map(|i| format!("X-Header-{}: {}\r\n", i, i)).collect();
+        let request = format!("GET /health HTTP/1.1\r\n{}\r\n", headers);
+        let response = send_raw(addr, request.as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_ambiguous_transfer_encoding_is_rejected() {
+        let addr = spawn_server(Server::new(Config::default())).await;
+
+        let response = send_raw(
+            addr,
+            b"POST /process HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"), "{}", response);
+
+        let response = send_raw(addr, b"POST /process HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\nhello").await;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"), "{}", response);
+
+        let response = send_raw(
+            addr,
+            b"POST /process HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 501 Not Implemented"), "{}", response);
+        assert!(response.contains("Connection: close"));
+    }
+}
@@ -0,0 +1,168 @@
+//! TLS setup for the server.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::{CipherSuite, SupportedCipherSuite, SupportedProtocolVersion};
+use serde::{Deserialize, Serialize};
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::ServerConfig;
+use crate::error::{Error, Result};
+
+/// TLS versions that can be set as `min_tls_version`.
+pub const TLS_VERSIONS: [&str; 2] = ["1.2", "1.3"];
+
+/// Which cipher suites the server offers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CipherPolicy {
+    /// TLS 1.3 suites, and only 256-bit AES-GCM or ChaCha20-Poly1305 for TLS 1.2
+    Modern,
+    /// Every suite rustls enables by default (the default)
+    #[default]
+    Compatible,
+}
+
+impl CipherPolicy {
+    fn cipher_suites(self) -> Vec<SupportedCipherSuite> {
+        let suites = rustls::DEFAULT_CIPHER_SUITES.iter().copied();
+        match self {
+            CipherPolicy::Compatible => suites.collect(),
+            CipherPolicy::Modern => suites
+                .filter(|suite| {
+                    !matches!(
+                        suite.suite(),
+                        CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
+                            | CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Protocol versions allowed by a minimum from [`TLS_VERSIONS`].
+fn protocol_versions(min_version: &str) -> Result<&'static [&'static SupportedProtocolVersion]> {
+    static TLS13_ONLY: &[&SupportedProtocolVersion] = &[&rustls::version::TLS13];
+    match min_version {
+        "1.2" => Ok(rustls::ALL_VERSIONS),
+        "1.3" => Ok(TLS13_ONLY),
+        other => Err(Error::Config(format!(
+            "Unsupported minimum TLS version {:?}; expected one of {:?}",
+            other, TLS_VERSIONS
+        ))),
+    }
+}
+
+/// Build the TLS acceptor for the configured certificate and key files.
+pub fn acceptor(config: &ServerConfig) -> Result<TlsAcceptor> {
+    let (Some(cert_path), Some(key_path)) = (&config.tls_cert_path, &config.tls_key_path) else {
+        return Err(Error::Config("TLS is enabled but the certificate or key path is not set".to_string()));
+    };
+
+    let tls_config = load_server_config(cert_path, key_path, &config.min_tls_version, config.cipher_policy)?;
+    Ok(TlsAcceptor::from(Arc::new(tls_config)))
+}
+
+/// Load a rustls server configuration from PEM certificate and key files.
+pub fn load_server_config(
+    cert_path: &Path,
+    key_path: &Path,
+    min_version: &str,
+    cipher_policy: CipherPolicy,
+) -> Result<rustls::ServerConfig> {
+    let cert_pem = std::fs::read(cert_path)
+        .map_err(|e| Error::Config(format!("Failed to read {}: {}", cert_path.display(), e)))?;
+    let key_pem = std::fs::read(key_path)
+        .map_err(|e| Error::Config(format!("Failed to read {}: {}", key_path.display(), e)))?;
+
+    server_config_from_pem(&cert_pem, &key_pem, min_version, cipher_policy)
+}
+
+/// Build a rustls server configuration from a PEM certificate chain and private key.
+///
+/// Only protocol versions from `min_version` up are accepted.
+pub fn server_config_from_pem(
+    cert_pem: &[u8],
+    key_pem: &[u8],
+    min_version: &str,
+    cipher_policy: CipherPolicy,
+) -> Result<rustls::ServerConfig> {
+    let versions = protocol_versions(min_version)?;
+
+    let certs: Vec<rustls::Certificate> = rustls_pemfile::certs(&mut &cert_pem[..])
+        .map_err(|e| Error::Config(format!("Invalid TLS certificate: {}", e)))?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+    if certs.is_empty() {
+        return Err(Error::Config("No TLS certificate found".to_string()));
+    }
+
+    let key = rustls_pemfile::read_all(&mut &key_pem[..])
+        .map_err(|e| Error::Config(format!("Invalid TLS private key: {}", e)))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(rustls::PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| Error::Config("No TLS private key found".to_string()))?;
+
+    let mut config = rustls::ServerConfig::builder()
+        .with_cipher_suites(&cipher_policy.cipher_suites())
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(versions)
+        .map_err(|e| Error::Config(format!("Invalid TLS policy: {}", e)))?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| Error::Config(format!("Invalid TLS certificate or key: {}", e)))?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::generate_self_signed_cert;
+
+    #[test]
+    fn test_server_config_from_pem() -> Result<()> {
+        let (cert, key) = generate_self_signed_cert(&["localhost"])?;
+        let config = server_config_from_pem(&cert, &key, "1.2", CipherPolicy::Compatible)?;
+        assert_eq!(config.alpn_protocols, [b"http/1.1".to_vec()]);
+
+        let from_pem = |cert: &[u8], key: &[u8]| server_config_from_pem(cert, key, "1.2", CipherPolicy::Modern);
+        assert!(matches!(from_pem(b"not a cert", &key), Err(Error::Config(_))));
+        assert!(matches!(from_pem(&cert, &cert), Err(Error::Config(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tls_policy() -> Result<()> {
+        let (cert, key) = generate_self_signed_cert(&["localhost"])?;
+        assert!(server_config_from_pem(&cert, &key, "1.3", CipherPolicy::Modern).is_ok());
+        for version in ["1.1", "1.0", "TLSv1.2", ""] {
+            let result = server_config_from_pem(&cert, &key, version, CipherPolicy::Compatible);
+            assert!(matches!(result, Err(Error::Config(message)) if message.contains("minimum TLS version")));
+        }
+
+        let modern = CipherPolicy::Modern.cipher_suites();
+        assert!(modern.len() < CipherPolicy::Compatible.cipher_suites().len());
+        assert!(modern.iter().all(|suite| !format!("{:?}", suite.suite()).contains("WITH_AES_128")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_acceptor_requires_paths() {
+        let mut config = ServerConfig { tls_enabled: true, ..ServerConfig::default() };
+        assert!(matches!(acceptor(&config), Err(Error::Config(_))));
+
+        config.tls_cert_path = Some("/nonexistent/cert.pem".into());
+        config.tls_key_path = Some("/nonexistent/key.pem".into());
+        assert!(matches!(acceptor(&config), Err(Error::Config(_))));
+    }
+}